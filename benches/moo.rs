@@ -10,5 +10,20 @@ pub fn bench(cr: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench);
+// a generated formula that repeats its subterms
+pub fn bench_cse(cr: &mut Criterion) {
+    let moo = Moo::new(|_| {});
+    let term = "(sin(x) * cos(x) + sqrt(x * x + 1))";
+    let source = format!("{t} * {t} + {t} / ({t} + 1) - cos(x) * {t} + sin(x) * sin(x)", t = term);
+    let program = moo.parse(&source).ok().unwrap().unwrap();
+    let compiled = program.compile();
+    cr.bench_function("cse_tree", |b| {
+        b.iter(|| program.run(black_box(0.5)));
+    });
+    cr.bench_function("cse_compiled", |b| {
+        b.iter(|| compiled.run(black_box(0.5)));
+    });
+}
+
+criterion_group!(benches, bench, bench_cse);
 criterion_main!(benches);
@@ -0,0 +1,82 @@
+// static facts about a program, read off the tree without evaluating it
+
+use crate::{constant, Expression, Primitive, Program};
+use std::collections::{BTreeMap, BTreeSet};
+
+impl Program {
+    /// The variables the program reads that it does not bind itself, `x` and `y`
+    /// included, so they can be checked against the data available before
+    /// evaluating. The constants `pi` and `e` are left out.
+    pub fn free_identifiers(&self) -> BTreeSet<String> {
+        let mut free = BTreeSet::new();
+        for definition in self.definitions.iter() {
+            let mut scope = definition.params.iter().map(String::as_str).collect();
+            definition.body.free_identifiers(&mut scope, &mut free);
+        }
+        let mut scope = Vec::new();
+        for assignment in self.assignments.iter() {
+            assignment.value.free_identifiers(&mut scope, &mut free);
+            scope.push(&assignment.name);
+        }
+        self.body.free_identifiers(&mut scope, &mut free);
+        free.into_iter().filter(|name| constant(name).is_none()).map(String::from).collect()
+    }
+    /// Every built-in and defined function called, with the number of places it is
+    /// called from. Operators such as `!` count as the function they call.
+    pub fn function_calls(&self) -> BTreeMap<String, usize> {
+        let mut calls = BTreeMap::new();
+        let mut nodes = Vec::new();
+        self.roots().for_each(|root| root.post_order(&mut nodes));
+        for node in nodes {
+            if let Expression::Primitive(Primitive::Function((name, ..)) | Primitive::Call((name, _))) = node {
+                *calls.entry(name.clone()).or_insert(0) += 1;
+            }
+        }
+        calls
+    }
+    /// The most nodes on a path from a root to a leaf, `1` for a lone number.
+    pub fn max_depth(&self) -> usize {
+        self.roots().map(Expression::depth).max().unwrap_or(0)
+    }
+    /// The expression nodes of the definitions, assignments and the body.
+    pub fn node_count(&self) -> usize {
+        self.roots().map(Expression::node_count).sum()
+    }
+    fn roots(&self) -> impl Iterator<Item = &Expression> {
+        let definitions = self.definitions.iter().map(|d| &d.body);
+        definitions.chain(self.assignments.iter().map(|a| &a.value)).chain([&self.body])
+    }
+}
+
+impl Expression {
+    fn depth(&self) -> usize {
+        1 + self.children().into_iter().map(Expression::depth).max().unwrap_or(0)
+    }
+    fn node_count(&self) -> usize {
+        1 + self.children().into_iter().map(Expression::node_count).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use crate::{Environment, Moo};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn analyse() {
+        let moo = Moo::new(|_| {});
+        let mut env = Environment::new();
+        env.set("rate", 0.5);
+        env.set("unused", 1.0);
+        let source = "f(t) = sin(t) * x; a = cos(y) + rate; let b = 2 in f(a) + f(b) * pi + sin(b)!";
+        let program = moo.parse_in(source, &env).ok().unwrap().unwrap();
+        assert_eq!(program.free_identifiers().into_iter().collect::<Vec<_>>(), ["rate", "x", "y"]);
+        let calls = BTreeMap::from([("cos", 1), ("f", 2), ("fact", 1), ("sin", 2)]);
+        assert_eq!(program.function_calls(), calls.into_iter().map(|(name, count)| (name.to_string(), count)).collect());
+        let program = moo.parse("sin(x) + 2 * y").ok().unwrap().unwrap();
+        assert_eq!((program.node_count(), program.max_depth()), (6, 3));
+        let program = moo.parse("f(t) = -(-(-t)); a = 1; f(a)").ok().unwrap().unwrap();
+        assert_eq!((program.node_count(), program.max_depth()), (7, 4));
+        assert!(program.free_identifiers().is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+// structural equality and canonical order
+// two trees are equal when they have the same shape, functions are told apart
+// by their registered name and custom operators by their symbol and fixity
+
+use crate::{CustomOperator, Expression, Operator, Primitive, Program};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+impl PartialEq for Primitive {
+    fn eq(&self, other: &Self) -> bool {
+        use Primitive::*;
+        match (self, other) {
+            (Function(a), Function(b)) => a.0 == b.0 && a.2 == b.2,
+            (Call(a), Call(b)) => a == b,
+            (Identifier(a), Identifier(b)) => a == b,
+            // by bits, so equality stays reflexive and agrees with `Hash`
+            (Number(a), Number(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Primitive {}
+
+impl Hash for Primitive {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Primitive::*;
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Function(func) => {
+                func.0.hash(state);
+                func.2.hash(state);
+            }
+            Call(call) => call.hash(state),
+            Identifier(ident) => ident.hash(state),
+            Number(num) => num.to_bits().hash(state),
+        }
+    }
+}
+
+impl PartialEq for CustomOperator {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbol == other.symbol && self.fixity == other.fixity
+    }
+}
+
+impl Eq for CustomOperator {}
+
+impl Hash for CustomOperator {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.symbol.hash(state);
+        self.fixity.hash(state);
+    }
+}
+
+// the statements only, where they came from in the source and the parser
+// options are not compared
+impl PartialEq for Program {
+    fn eq(&self, other: &Self) -> bool {
+        self.definitions == other.definitions && self.assignments == other.assignments && self.body == other.body
+    }
+}
+
+impl Eq for Program {}
+
+impl Hash for Program {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.definitions.hash(state);
+        self.assignments.hash(state);
+        self.body.hash(state);
+    }
+}
+
+impl Operator {
+    // `a op b` is `b op a` for every value, `and` and `or` are not swapped since
+    // only their left side is always evaluated
+    fn is_commutative(&self) -> bool {
+        use Operator::*;
+        matches!(self, Add | Mult | BitAnd | BitOr | BitXor | Eq | Ne)
+    }
+}
+
+impl Expression {
+    /// Puts the operands of `+`, `*`, `==`, `!=` and the bitwise operators in a
+    /// fixed order, so `x + 1` and `1 + x` become equal. Chains are not regrouped,
+    /// `(a + b) + c` keeps its grouping and so its rounding.
+    pub fn canonicalize(&mut self) {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Function(func)) => func.2.iter_mut().for_each(Expression::canonicalize),
+            Primitive(crate::Primitive::Call(call)) => call.1.iter_mut().for_each(Expression::canonicalize),
+            Primitive(_) | Error => {}
+            ExpressionOperation(expr) => {
+                expr.0.canonicalize();
+                expr.2.canonicalize();
+                if expr.1.is_commutative() && expr.0.order(&expr.2) == Ordering::Greater {
+                    std::mem::swap(&mut expr.0, &mut expr.2);
+                }
+            }
+            UnaryOperation(expr) => expr.1.canonicalize(),
+            CustomOperation(expr) => expr.1.iter_mut().for_each(Expression::canonicalize),
+            Conditional(expr) => {
+                expr.0.canonicalize();
+                expr.1.canonicalize();
+                expr.2.canonicalize();
+            }
+            Piecewise(expr) => {
+                for (condition, branch) in expr.0.iter_mut() {
+                    condition.canonicalize();
+                    branch.canonicalize();
+                }
+                if let Some(otherwise) = &mut expr.1 {
+                    otherwise.canonicalize();
+                }
+            }
+            Let(expr) => {
+                expr.1.canonicalize();
+                expr.2.canonicalize();
+            }
+        }
+    }
+    // numbers, then names, calls and the operations, each compared field by field
+    fn order(&self, other: &Expression) -> Ordering {
+        use Expression::*;
+        let rank = |expr: &Expression| match expr {
+            Primitive(crate::Primitive::Number(_)) => 0,
+            Primitive(crate::Primitive::Identifier(_)) => 1,
+            Primitive(crate::Primitive::Function(_)) => 2,
+            Primitive(crate::Primitive::Call(_)) => 3,
+            UnaryOperation(_) => 4,
+            ExpressionOperation(_) => 5,
+            CustomOperation(_) => 6,
+            Conditional(_) => 7,
+            Piecewise(_) => 8,
+            Let(_) => 9,
+            Error => 10,
+        };
+        rank(self).cmp(&rank(other)).then_with(|| match (self, other) {
+            (Primitive(crate::Primitive::Number(a)), Primitive(crate::Primitive::Number(b))) => a.total_cmp(b),
+            (Primitive(crate::Primitive::Identifier(a)), Primitive(crate::Primitive::Identifier(b))) => a.cmp(b),
+            (Primitive(crate::Primitive::Function(a)), Primitive(crate::Primitive::Function(b))) => {
+                a.0.cmp(&b.0).then_with(|| order_all(&a.2, &b.2))
+            }
+            (Primitive(crate::Primitive::Call(a)), Primitive(crate::Primitive::Call(b))) => {
+                a.0.cmp(&b.0).then_with(|| order_all(&a.1, &b.1))
+            }
+            (UnaryOperation(a), UnaryOperation(b)) => a.0.cmp(&b.0).then_with(|| a.1.order(&b.1)),
+            (ExpressionOperation(a), ExpressionOperation(b)) => {
+                a.1.cmp(&b.1).then_with(|| a.0.order(&b.0)).then_with(|| a.2.order(&b.2))
+            }
+            (CustomOperation(a), CustomOperation(b)) => {
+                a.0.symbol.cmp(&b.0.symbol).then_with(|| a.0.fixity.cmp(&b.0.fixity)).then_with(|| order_all(&a.1, &b.1))
+            }
+            (Conditional(a), Conditional(b)) => {
+                a.0.order(&b.0).then_with(|| a.1.order(&b.1)).then_with(|| a.2.order(&b.2))
+            }
+            (Piecewise(a), Piecewise(b)) => {
+                let conditions = a.0.iter().zip(b.0.iter()).map(|(a, b)| a.0.order(&b.0).then_with(|| a.1.order(&b.1)));
+                let otherwise = match (&a.1, &b.1) {
+                    (Some(a), Some(b)) => a.order(b),
+                    (a, b) => a.is_some().cmp(&b.is_some()),
+                };
+                conditions.fold(Ordering::Equal, Ordering::then).then(a.0.len().cmp(&b.0.len())).then(otherwise)
+            }
+            (Let(a), Let(b)) => a.0.cmp(&b.0).then_with(|| a.1.order(&b.1)).then_with(|| a.2.order(&b.2)),
+            _ => Ordering::Equal,
+        })
+    }
+}
+
+fn order_all(a: &[Expression], b: &[Expression]) -> Ordering {
+    a.iter().zip(b.iter()).map(|(a, b)| a.order(b)).fold(Ordering::Equal, Ordering::then).then(a.len().cmp(&b.len()))
+}
+
+impl Program {
+    /// [`Expression::canonicalize`] for every definition, assignment and the body,
+    /// the nodes no longer have [`Program::spans`] afterwards.
+    pub fn canonicalize(&mut self) {
+        self.definitions.iter_mut().for_each(|definition| definition.body.canonicalize());
+        self.assignments.iter_mut().for_each(|assignment| assignment.value.canonicalize());
+        self.body.canonicalize();
+        self.spans = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod canonical_tests {
+    use crate::{Expression, Moo, Program};
+    use std::collections::HashSet;
+
+    fn canonical(moo: &Moo, source: &str) -> Expression {
+        let mut program = moo.parse(source).ok().unwrap().unwrap();
+        program.canonicalize();
+        program.body
+    }
+
+    #[test]
+    fn structural_equality() {
+        let moo = Moo::new(|_| {});
+        let parse = |source: &str| moo.parse(source).ok().unwrap().unwrap();
+        assert_eq!(parse("sin(x) * 2 + 1").body, parse("sin(x)*2+1").body);
+        // `!` calls the registered `fact`
+        assert_eq!(parse("3!").body, parse("fact(3)").body);
+        assert_ne!(parse("x + 1").body, parse("1 + x").body);
+        assert_ne!(parse("sin(x)").body, parse("cos(x)").body);
+        let program = parse("f(t) = t^2; f(x) + f(2)");
+        assert_eq!(program.body.clone(), program.body);
+        assert_eq!(program.definitions[0].body, parse("f(t) = t ^ 2; 0").definitions[0].body);
+        // programs compare every statement
+        assert_eq!(program, parse("f(t) = t ^ 2\nf(x) + f(2)"));
+        assert_ne!(parse("f(t) = t; f(x)"), parse("f(t) = t ^ 2; f(x)"));
+        assert_ne!(parse("f(t) = t; f(x)"), parse("f(s) = s; f(x)"));
+        assert_ne!(parse("a = 1; a + x"), parse("a = 2; a + x"));
+        // `|x|` is `abs(x)` with whatever `abs` is registered
+        let moo = Moo::new(|functions| {
+            functions.insert("abs", |v| v * 10.0);
+        });
+        let (bars, call) = (moo.parse("|x|").ok().unwrap().unwrap(), moo.parse("abs(x)").ok().unwrap().unwrap());
+        assert_eq!(bars, call);
+        assert_eq!(bars.run(-2.0), call.run(-2.0));
+    }
+
+    #[test]
+    fn canonical_order() {
+        let moo = Moo::new(|_| {});
+        assert_eq!(canonical(&moo, "x + 1"), canonical(&moo, "1 + x"));
+        assert_eq!(canonical(&moo, "2 * (x + y) == sin(x) * 3"), canonical(&moo, "3 * sin(x) == (y + x) * 2"));
+        assert_ne!(canonical(&moo, "x - 1"), canonical(&moo, "1 - x"));
+        assert_ne!(canonical(&moo, "x > 0 and y > 0"), canonical(&moo, "y > 0 and x > 0"));
+        assert_eq!(canonical(&moo, "x * y + 1").to_string(), "1 + x * y");
+        let bodies: HashSet<Expression> = ["x + 1", "1 + x", "(1 + x)", "x + 2"].iter().map(|source| canonical(&moo, source)).collect();
+        assert_eq!(bodies.len(), 2);
+        let programs: HashSet<Program> = ["f(t) = t + 1; f(x)", "f(t) = 1 + t; f(x)", "f(t) = 2 + t; f(x)"]
+            .iter()
+            .map(|source| {
+                let mut program = moo.parse(source).ok().unwrap().unwrap();
+                program.canonicalize();
+                program
+            })
+            .collect();
+        assert_eq!(programs.len(), 2);
+        // values and spans
+        let mut program = moo.parse("sin(x) * (x + 3) - x * 2").ok().unwrap().unwrap();
+        let value = program.run(0.7);
+        program.canonicalize();
+        assert_eq!(program.run(0.7), value);
+        assert_eq!(program.spans().get(&program.body), None);
+    }
+}
@@ -0,0 +1,369 @@
+// compiled programs
+// the expression trees are hash-consed into graphs where every distinct
+// subexpression is one node, so a subterm written twice is computed once
+
+use crate::{boolean, constant, truth, Config, Environment, Evaluation, Expression, Fixity, Func, Operator, Program, UnaryOperator};
+use std::collections::HashMap;
+
+/// A [`Program`] with common subexpressions merged, built with [`Program::compile`].
+///
+/// A repeated subexpression such as the `sin(x)` of `sin(x) * sin(x) + cos(x) * sin(x)`
+/// is computed once per run and once per call inside a function. Conditionals,
+/// piecewise branches and `and` / `or` still only evaluate what they need.
+/// Registered functions are taken to be pure, a call with the same arguments
+/// is assumed to give the same value. [`Compiled::eval`] reads `y` and the
+/// variables of an [`Environment`] as [`Program::eval`] does.
+#[derive(Debug)]
+pub struct Compiled {
+    main: Graph,
+    /// Values of the assignments, evaluated in order before `body`.
+    assignments: Vec<usize>,
+    names: Vec<String>,
+    body: usize,
+    /// The graph of every definition and the node of its body.
+    definitions: Vec<(Graph, usize)>,
+    functions: Vec<Func>,
+    config: Config,
+}
+
+// hash-consed nodes of one scope, children before their parents
+#[derive(Debug, Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, usize>,
+}
+
+// a subexpression, operands are node ids of the same graph
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    // the bits, so equal literals hash the same
+    Number(u64),
+    X,
+    Y,
+    Param(usize),
+    // a variable of the environment or else a constant such as `pi`
+    Variable(String),
+    Unary(UnaryOperator, usize),
+    Binary(Operator, usize, usize),
+    // index into `Compiled::functions`
+    Function(usize, Vec<usize>),
+    // index into `Compiled::definitions`
+    Call(usize, Vec<usize>),
+    Conditional(usize, usize, usize),
+    Piecewise(Vec<(usize, usize)>, Option<usize>),
+    // the value is evaluated first as the tree does, the body reads it by its id
+    Let(usize, usize),
+    Error,
+}
+
+impl Graph {
+    fn intern(&mut self, node: Node) -> usize {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        self.nodes.push(node.clone());
+        self.ids.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+}
+
+// state of the compilation, functions are told apart by name and operators by symbol
+struct Compiler<'p> {
+    program: &'p Program,
+    functions: Vec<Func>,
+    function_ids: HashMap<(String, Option<Fixity>), usize>,
+}
+
+impl<'p> Compiler<'p> {
+    fn function(&mut self, name: &str, fixity: Option<Fixity>, func: Func) -> usize {
+        let functions = &mut self.functions;
+        *self.function_ids.entry((name.to_string(), fixity)).or_insert_with(|| {
+            functions.push(func);
+            functions.len() - 1
+        })
+    }
+    // `scope` holds the node of every variable in scope, innermost last
+    fn compile(&mut self, expr: &'p Expression, graph: &mut Graph, scope: &mut Vec<(&'p str, usize)>) -> usize {
+        use Expression::*;
+        let node = match expr {
+            Primitive(crate::Primitive::Number(num)) => Node::Number(num.to_bits()),
+            Primitive(crate::Primitive::Identifier(ident)) => {
+                if let Some((_, id)) = scope.iter().rev().find(|(name, _)| name == ident) {
+                    return *id;
+                }
+                match ident.as_str() {
+                    "x" => Node::X,
+                    "y" => Node::Y,
+                    _ => Node::Variable(ident.clone()),
+                }
+            }
+            Primitive(crate::Primitive::Function(func)) => {
+                let args = self.compile_all(&func.2, graph, scope);
+                Node::Function(self.function(&func.0, None, func.1), args)
+            }
+            Primitive(crate::Primitive::Call(call)) => {
+                let args = self.compile_all(&call.1, graph, scope);
+                match self.program.definitions.iter().position(|d| d.name == call.0) {
+                    Some(definition) => Node::Call(definition, args),
+                    None => Node::Error,
+                }
+            }
+            ExpressionOperation(expr) => {
+                let left = self.compile(&expr.0, graph, scope);
+                let right = self.compile(&expr.2, graph, scope);
+                Node::Binary(expr.1, left, right)
+            }
+            UnaryOperation(expr) => Node::Unary(expr.0, self.compile(&expr.1, graph, scope)),
+            CustomOperation(expr) => {
+                let args = self.compile_all(&expr.1, graph, scope);
+                Node::Function(self.function(&expr.0.symbol, Some(expr.0.fixity), expr.0.function), args)
+            }
+            Conditional(expr) => {
+                let condition = self.compile(&expr.0, graph, scope);
+                let then = self.compile(&expr.1, graph, scope);
+                Node::Conditional(condition, then, self.compile(&expr.2, graph, scope))
+            }
+            Piecewise(expr) => {
+                let branches = expr.0.iter().map(|(condition, branch)| {
+                    (self.compile(condition, graph, scope), self.compile(branch, graph, scope))
+                });
+                let branches = branches.collect();
+                let otherwise = expr.1.as_ref().map(|otherwise| self.compile(otherwise, graph, scope));
+                Node::Piecewise(branches, otherwise)
+            }
+            Let(expr) => {
+                let value = self.compile(&expr.1, graph, scope);
+                scope.push((&expr.0, value));
+                let body = self.compile(&expr.2, graph, scope);
+                scope.pop();
+                Node::Let(value, body)
+            }
+            Error => Node::Error,
+        };
+        graph.intern(node)
+    }
+    fn compile_all(&mut self, exprs: &'p [Expression], graph: &mut Graph, scope: &mut Vec<(&'p str, usize)>) -> Vec<usize> {
+        exprs.iter().map(|expr| self.compile(expr, graph, scope)).collect()
+    }
+}
+
+impl Program {
+    /// Merges the repeated subexpressions of the program, see [`Compiled`].
+    pub fn compile(&self) -> Compiled {
+        let mut compiler = Compiler { program: self, functions: Vec::new(), function_ids: HashMap::new() };
+        let mut definitions = Vec::new();
+        for definition in self.definitions.iter() {
+            let mut graph = Graph::default();
+            let mut scope = Vec::new();
+            for (i, param) in definition.params.iter().enumerate() {
+                scope.push((param.as_str(), graph.intern(Node::Param(i))));
+            }
+            let body = compiler.compile(&definition.body, &mut graph, &mut scope);
+            definitions.push((graph, body));
+        }
+        let mut main = Graph::default();
+        let mut scope = Vec::new();
+        let mut assignments = Vec::new();
+        for assignment in self.assignments.iter() {
+            let value = compiler.compile(&assignment.value, &mut main, &mut scope);
+            scope.push((&assignment.name, value));
+            assignments.push(value);
+        }
+        let body = compiler.compile(&self.body, &mut main, &mut scope);
+        let names = self.assignments.iter().map(|assignment| assignment.name.clone()).collect();
+        Compiled { main, assignments, names, body, definitions, functions: compiler.functions, config: self.config }
+    }
+}
+
+// what a run reads, `env` is the one of `Compiled::eval`
+struct Input<'e> {
+    x: f64,
+    y: f64,
+    env: Option<&'e Environment>,
+}
+
+// values of one run of a graph, a node is computed the first time it is needed
+struct Frame<'c> {
+    graph: &'c Graph,
+    params: Vec<f64>,
+    values: Vec<Option<f64>>,
+    // nodes being computed, across calls
+    depth: usize,
+}
+
+impl Compiled {
+    /// Evaluates at `x`, a domain error gives `NaN`.
+    pub fn run(&self, x: f64) -> f64 {
+        self.try_run(x).unwrap_or(f64::NAN)
+    }
+    /// Evaluates at `x` like [`Program::try_run`], `y` is `0`.
+    pub fn try_run(&self, x: f64) -> Result<f64, &'static str> {
+        let input = Input { x, y: 0.0, env: None };
+        let mut frame = self.frame();
+        for &assignment in self.assignments.iter() {
+            self.value(assignment, &mut frame, &input)?;
+        }
+        self.value(self.body, &mut frame, &input)
+    }
+    /// Runs the statements against `env` like [`Program::eval`], reading `x`, `y`
+    /// and the variables of [`Moo::parse_in`](crate::Moo::parse_in) from it.
+    pub fn eval(&self, env: &mut Environment) -> Result<Evaluation, &'static str> {
+        let variables = env.clone();
+        let input = Input { x: env.get("x").unwrap_or(0.0), y: env.get("y").unwrap_or(0.0), env: Some(&variables) };
+        let mut frame = self.frame();
+        let mut assignments = Vec::new();
+        for (&assignment, name) in self.assignments.iter().zip(self.names.iter()) {
+            let value = self.value(assignment, &mut frame, &input)?;
+            env.set(name, value);
+            assignments.push((name.clone(), value));
+        }
+        let value = self.value(self.body, &mut frame, &input)?;
+        Ok(Evaluation { assignments, value })
+    }
+    fn frame(&self) -> Frame<'_> {
+        Frame { graph: &self.main, params: Vec::new(), values: vec![None; self.main.nodes.len()], depth: 0 }
+    }
+    /// Number of distinct subexpressions, counted once per function definition.
+    pub fn node_count(&self) -> usize {
+        self.main.nodes.len() + self.definitions.iter().map(|(graph, _)| graph.nodes.len()).sum::<usize>()
+    }
+    fn value(&self, id: usize, frame: &mut Frame, input: &Input) -> Result<f64, &'static str> {
+        if let Some(value) = frame.values[id] {
+            return Ok(value);
+        }
+        if frame.depth >= self.config.max_call_depth {
+            return Err("Recursion depth exceeded");
+        }
+        frame.depth += 1;
+        let value = self.compute(id, frame, input);
+        frame.depth -= 1;
+        let value = value?;
+        frame.values[id] = Some(value);
+        Ok(value)
+    }
+    fn compute(&self, id: usize, frame: &mut Frame, input: &Input) -> Result<f64, &'static str> {
+        let value = match &frame.graph.nodes[id] {
+            Node::Number(bits) => f64::from_bits(*bits),
+            Node::X => input.x,
+            Node::Y => input.y,
+            Node::Param(i) => frame.params[*i],
+            Node::Variable(name) => match input.env.and_then(|env| env.get(name)).or_else(|| constant(name)) {
+                Some(value) => value,
+                None => return Err("Unknown variable"),
+            },
+            Node::Unary(op, operand) => {
+                let value = self.value(*operand, frame, input)?;
+                match op {
+                    UnaryOperator::Neg => -value,
+                    UnaryOperator::Not => boolean(!truth(value)),
+                }
+            }
+            Node::Binary(op, left, right) => {
+                let left = self.value(*left, frame, input)?;
+                match op {
+                    Operator::And if !truth(left) => 0.0,
+                    Operator::Or if truth(left) => 1.0,
+                    _ => {
+                        let right = self.value(*right, frame, input)?;
+                        op.perform(left, right, &self.config)?
+                    }
+                }
+            }
+            Node::Function(func, args) => {
+                let args = args.iter().map(|&arg| self.value(arg, frame, input)).collect::<Result<Vec<f64>, _>>()?;
+                self.functions[*func].call(&args)?
+            }
+            Node::Call(definition, args) => {
+                let params = args.iter().map(|&arg| self.value(arg, frame, input)).collect::<Result<Vec<f64>, _>>()?;
+                let (graph, body) = &self.definitions[*definition];
+                let mut call = Frame { graph, params, values: vec![None; graph.nodes.len()], depth: frame.depth };
+                self.value(*body, &mut call, input)?
+            }
+            Node::Conditional(condition, then, otherwise) => {
+                let branch = if truth(self.value(*condition, frame, input)?) { then } else { otherwise };
+                self.value(*branch, frame, input)?
+            }
+            Node::Piecewise(branches, otherwise) => {
+                let mut taken = *otherwise;
+                for (condition, branch) in branches.iter() {
+                    if truth(self.value(*condition, frame, input)?) {
+                        taken = Some(*branch);
+                        break;
+                    }
+                }
+                self.value(taken.ok_or("No piecewise branch matched")?, frame, input)?
+            }
+            Node::Let(value, body) => {
+                self.value(*value, frame, input)?;
+                self.value(*body, frame, input)?
+            }
+            Node::Error => return Err("Program has syntax errors"),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod compile_tests {
+    use crate::{Environment, Moo};
+
+    #[test]
+    fn compile_shares_subexpressions() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("sin(x) * sin(x) + cos(x) * sin(x)").ok().unwrap().unwrap();
+        let compiled = program.compile();
+        // x, sin(x), cos(x), the two products and the sum
+        assert_eq!(compiled.node_count(), 6);
+        assert_eq!(compiled.run(0.5), program.run(0.5));
+        // the two `a` are different variables
+        let program = moo.parse("(let a = 1 in a + x) * (let a = 2 in a + x)").ok().unwrap().unwrap();
+        assert_eq!(program.compile().run(1.0), 6.0);
+    }
+
+    #[test]
+    fn compile_matches_tree() {
+        let mut moo = Moo::new(|_| {});
+        moo.register(crate::special::register);
+        let sources = [
+            "f(n) = n <= 1 ? 1 : n * f(n - 1); f(x) + f(x)",
+            "f(t) = t^2 + t^2; g(a, b) = f(a) - f(b); g(x, 3) * g(x, 3)",
+            "a = x^2; b = a + sin(a); { b if x < 0; a * b if x < 2; -b otherwise }",
+            "x > 1 and sqrt(x - 1) > 1 or (x + 1)! > 2",
+            "let a = x * 2 in let b = a * a in b + a + x * 2",
+            "2 ^ 3 ^ x % 7 - |x - 3| + nCr(5, 2) * erf(x)",
+            "{ 1 if x < 0 }",
+            "(x - 3)!",
+        ];
+        for source in sources {
+            let program = moo.parse(source).ok().unwrap().unwrap();
+            let compiled = program.compile();
+            for x in [-1.5, 0.0, 1.0, 2.5, 4.0] {
+                assert_eq!(compiled.try_run(x).map(f64::to_bits), program.try_run(x).map(f64::to_bits), "{} at {}", source, x);
+            }
+        }
+        let program = moo.parse("f(n) = n <= 1 ? 1 : n * f(n - 1); f(x)").ok().unwrap().unwrap();
+        assert_eq!(program.compile().try_run(500.0), Err("Recursion depth exceeded"));
+        // `|x|` calls the registered `abs` as `abs(x)` does
+        let moo = Moo::new(|functions| {
+            functions.insert("abs", |v| v * 10.0);
+        });
+        let program = moo.parse("|x| + abs(x)").ok().unwrap().unwrap();
+        assert_eq!(program.run(-2.0), -40.0);
+        assert_eq!(program.compile().run(-2.0), -40.0);
+    }
+
+    #[test]
+    fn compile_eval() {
+        let moo = Moo::new(|_| {});
+        let mut env = Environment::new();
+        env.set("rate", 0.5);
+        env.set("y", 3.0);
+        env.set("x", 2.0);
+        let program = moo.parse_in("a = rate * y; b = a + x * pi; b - y", &env).ok().unwrap().unwrap();
+        let expected = program.eval(&mut env.clone()).unwrap();
+        assert_eq!(program.compile().eval(&mut env), Ok(expected));
+        assert_eq!(env.get("b"), Some(1.5 + 2.0 * std::f64::consts::PI));
+        // without an environment only `x` is given
+        assert_eq!(program.compile().try_run(2.0), Err("Unknown variable"));
+    }
+}
@@ -0,0 +1,285 @@
+// concrete syntax tree
+// every byte of the source is kept, either as the text of a token or as the
+// trivia in front of it, so printing the tree gives the source back
+
+use crate::{abs_bars, Cursor, Environment, Moo, MooError, Program, Span, Token, Tokenizer};
+use std::fmt::{Display, Formatter, Result};
+
+/// Source text between tokens that the parser skips.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    /// Spaces and tabs, a line break outside brackets is a [`Token::Newline`] instead.
+    Whitespace(String),
+    /// `# ...` up to the line break or `/* ... */`, with the markers.
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    /// A malformed literal such as `0b2` is a `Token::Unknown('\u{fffd}')`,
+    /// [`Moo::lower`] fails on it.
+    pub token: Token,
+    /// The token as written, `1_000` and `1000` are both `Token::Number(1000.0)`.
+    pub text: String,
+    pub span: Span,
+    pub leading: Vec<Trivia>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Program,
+    /// The tokens between two `;` or newlines outside brackets.
+    Statement,
+    /// `( ... )`, `{ ... }` or `| ... |` with its brackets.
+    Group,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+/// Lossless tree of a source, `cst.to_string()` gives the source back byte for byte.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst {
+    pub root: SyntaxNode,
+    /// Trivia after the last token.
+    pub trailing: Vec<Trivia>,
+}
+
+impl SyntaxNode {
+    fn new(kind: NodeKind) -> SyntaxNode {
+        SyntaxNode { kind, children: Vec::new() }
+    }
+    /// Tokens of the node in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.collect(&mut tokens);
+        tokens
+    }
+    fn collect<'c>(&'c self, tokens: &mut Vec<&'c SyntaxToken>) {
+        for child in self.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => node.collect(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+}
+
+impl<'a> Moo<'a> {
+    /// Builds the lossless tree of `source`, see [`Moo::lower`] for the AST.
+    pub fn parse_cst(&self, source: &str) -> std::result::Result<Cst, MooError> {
+        self.check_length(source).map_err(|message| MooError { message, span: None })?;
+        let mut tokenizer = Tokenizer::with_symbols(source, self.operators.symbols());
+        let mut tokens: Vec<(Token, Span)> = Vec::new();
+        loop {
+            match tokenizer.next() {
+                Ok(Some(token)) => {
+                    self.check_tokens(&tokens).map_err(|message| MooError { message, span: Some(token.1) })?;
+                    tokens.push(token);
+                }
+                Ok(None) => break,
+                // the literal is kept for `lower` to fail on
+                Err(message) => {
+                    let error = tokenizer.literal_error(message);
+                    tokens.push((Token::Unknown('\u{fffd}'), error.span.unwrap()));
+                }
+            }
+        }
+        abs_bars(&mut tokens, self.config.integer_mode)?;
+        // innermost open node last, the program and its current statement at the bottom
+        let mut stack = vec![SyntaxNode::new(NodeKind::Program), SyntaxNode::new(NodeKind::Statement)];
+        let mut end = 0;
+        for (token, span) in tokens {
+            let syntax = SyntaxToken {
+                text: source[span.start..span.end].to_string(),
+                leading: trivia(&source[end..span.start]),
+                token,
+                span,
+            };
+            end = span.end;
+            match syntax.token {
+                Token::LParent | Token::LBrace | Token::AbsOpen => {
+                    let mut group = SyntaxNode::new(NodeKind::Group);
+                    group.children.push(SyntaxElement::Token(syntax));
+                    stack.push(group);
+                }
+                Token::RParent | Token::RBrace | Token::AbsClose if stack.len() > 2 => {
+                    let mut group = stack.pop().unwrap();
+                    group.children.push(SyntaxElement::Token(syntax));
+                    stack.last_mut().unwrap().children.push(SyntaxElement::Node(group));
+                }
+                Token::Semicolon | Token::Newline if stack.len() == 2 => {
+                    close_statement(&mut stack);
+                    stack[0].children.push(SyntaxElement::Token(syntax));
+                    stack.push(SyntaxNode::new(NodeKind::Statement));
+                }
+                _ => stack.last_mut().unwrap().children.push(SyntaxElement::Token(syntax)),
+            }
+        }
+        // brackets left open end with the source
+        while stack.len() > 2 {
+            let group = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(SyntaxElement::Node(group));
+        }
+        close_statement(&mut stack);
+        Ok(Cst {
+            root: stack.pop().unwrap(),
+            trailing: trivia(&source[end..]),
+        })
+    }
+    /// Parses the tokens of `cst` into a [`Program`], the same as [`Moo::parse`]
+    /// on the source the tree was built from.
+    pub fn lower(&self, cst: &Cst) -> std::result::Result<Option<Program>, &'static str> {
+        let mut tokens: Vec<(Token, Span)> = Vec::new();
+        for syntax in cst.root.tokens() {
+            if let Token::Unknown(_) = syntax.token {
+                // a malformed literal fails to read again
+                let mut tokenizer = Tokenizer::with_symbols(&syntax.text, self.operators.symbols());
+                return Err(tokenizer.next().err().unwrap_or("Unexpected character"));
+            }
+            tokens.push((syntax.token.clone(), syntax.span));
+        }
+        self.ast_program(&mut Cursor::new(&tokens, false), &Environment::default())
+    }
+}
+
+// moves the open statement into the program unless it is empty
+fn close_statement(stack: &mut Vec<SyntaxNode>) {
+    let statement = stack.pop().unwrap();
+    if !statement.children.is_empty() {
+        stack[0].children.push(SyntaxElement::Node(statement));
+    }
+}
+
+// splits the text between two tokens into whitespace and comments
+fn trivia(mut text: &str) -> Vec<Trivia> {
+    let mut trivia = Vec::new();
+    while !text.is_empty() {
+        let length = if text.starts_with('#') {
+            text.find('\n').unwrap_or(text.len())
+        } else if let Some(comment) = text.strip_prefix("/*") {
+            comment.find("*/").map_or(text.len(), |i| i + 4)
+        } else {
+            text.find(['#', '/']).filter(|&i| i > 0).unwrap_or(text.len())
+        };
+        let (piece, rest) = text.split_at(length);
+        if piece.starts_with(['#', '/']) {
+            trivia.push(Trivia::Comment(piece.to_string()));
+        } else {
+            trivia.push(Trivia::Whitespace(piece.to_string()));
+        }
+        text = rest;
+    }
+    trivia
+}
+
+impl Display for Trivia {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Trivia::Whitespace(text) | Trivia::Comment(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl Display for SyntaxToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for trivia in self.leading.iter() {
+            write!(f, "{}", trivia)?;
+        }
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for child in self.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => write!(f, "{}", node)?,
+                SyntaxElement::Token(token) => write!(f, "{}", token)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Cst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.root)?;
+        for trivia in self.trailing.iter() {
+            write!(f, "{}", trivia)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cst_tests {
+    use super::*;
+
+    #[test]
+    fn cst_round_trip() {
+        let moo = Moo::new(|_| {});
+        let sources = [
+            "  1_000 +\t0x1F * x  ",
+            "f(t) = t^2;\n\n  a = f( x )\n{ 1 if x < 0;\n  2 otherwise }\n",
+            "|x − 3| × π²\u{a0}",
+            "วัว = 1.50e+3; วัว $ (",
+            "# area\nr = 2 /* cm */ # radius\n/* one\ntwo */ pi * r^2 # done",
+            "",
+        ];
+        for source in sources {
+            assert_eq!(moo.parse_cst(source).unwrap().to_string(), source);
+        }
+    }
+
+    #[test]
+    fn cst_structure() {
+        let moo = Moo::new(|_| {});
+        let cst = moo.parse_cst("a = (1_000 + x)\nb = {a if x > 0; 0 otherwise}; b").unwrap();
+        let kinds: Vec<NodeKind> = cst.root.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node.kind),
+            SyntaxElement::Token(_) => None,
+        }).collect();
+        assert_eq!(kinds, vec![NodeKind::Statement; 3]);
+        let tokens = cst.root.tokens();
+        assert_eq!(tokens[3].text, "1_000");
+        assert_eq!(tokens[3].token, Token::Number(1000.0));
+        assert_eq!(tokens[4].leading, vec![Trivia::Whitespace(" ".to_string())]);
+        let cst = moo.parse_cst("x /* a */ # b\n+ 1").unwrap();
+        let tokens = cst.root.tokens();
+        assert_eq!(tokens[1].token, Token::Newline);
+        assert_eq!(tokens[1].leading, vec![
+            Trivia::Whitespace(" ".to_string()),
+            Trivia::Comment("/* a */".to_string()),
+            Trivia::Whitespace(" ".to_string()),
+            Trivia::Comment("# b".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn cst_lower() {
+        let moo = Moo::new(|_| {});
+        let source = "f(t) = t * 2\na = f(x) + |x - 4|\n{ a if x > 0; 0 otherwise }";
+        let lowered = moo.lower(&moo.parse_cst(source).unwrap()).ok().unwrap().unwrap();
+        let parsed = moo.parse(source).ok().unwrap().unwrap();
+        assert_eq!(lowered.to_string(), parsed.to_string());
+        assert_eq!(lowered.run(1.0), 5.0);
+        // a malformed literal stays in the tree and fails to lower
+        let cst = moo.parse_cst("1 + 0b2 * x").unwrap();
+        assert_eq!(cst.to_string(), "1 + 0b2 * x");
+        assert_eq!(cst.root.tokens()[2].text, "0b2");
+        assert_eq!(moo.lower(&cst).err().unwrap(), "Invalid digit for the number base");
+        assert_eq!(moo.lower(&moo.parse_cst("x + /* 1").unwrap()).err().unwrap(), "Unterminated block comment");
+        assert_eq!(moo.lower(&moo.parse_cst("x $").unwrap()).err().unwrap(), "Unexpected character");
+        assert_eq!(moo.parse_cst("|a|b|c|").err().unwrap().span.unwrap().start, 2);
+    }
+}
@@ -0,0 +1,193 @@
+// symbolic differentiation
+// the derivative is built from the tree with the sum, product, quotient, power
+// and chain rules, operands of 0 and 1 are folded away as it is built
+
+use crate::{Expression, Func, Operator, Primitive, Program, UnaryOperator};
+
+impl Program {
+    /// The derivative of the program by `var`, `x^2 * sin(x)` gives
+    /// `2 * x * sin(x) + x ^ 2 * cos(x)`.
+    ///
+    /// Conditionals and piecewise expressions are differentiated branch by
+    /// branch under the same conditions, comparisons and boolean operators are
+    /// constant between the points where they change. `sin`, `cos`, `sqrt` and
+    /// `abs` are taken to be the built-in functions of that name. Fails on any
+    /// other function, on a power whose exponent depends on `var` unless its base
+    /// is `e`, on `%`, `//` and the bitwise operators, and on programs with
+    /// assignments, `let`s or calls of their own functions.
+    pub fn derivative(&self, var: &str) -> Result<Program, &'static str> {
+        if !self.assignments.is_empty() {
+            return Err("Cannot differentiate a program with assignments");
+        }
+        let mut program = self.clone();
+        program.body = self.body.derivative(var)?;
+        program.spans = Default::default();
+        Ok(program)
+    }
+}
+
+impl Expression {
+    fn derivative(&self, var: &str) -> Result<Expression, &'static str> {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Number(_)) => Ok(number(0.0)),
+            Primitive(crate::Primitive::Identifier(ident)) => Ok(number(if ident == var { 1.0 } else { 0.0 })),
+            Primitive(crate::Primitive::Function((name, func, args))) if args.len() == 1 => {
+                let (u, du) = (&args[0], args[0].derivative(var)?);
+                let outer = match name.as_str() {
+                    "sin" => function("cos", f64::cos, u),
+                    "cos" => negate(function("sin", f64::sin, u)),
+                    // 1 / (2 sqrt(u))
+                    "sqrt" => binary(number(1.0), Operator::Div, binary(number(2.0), Operator::Mult, self.clone())),
+                    // u / |u|
+                    "abs" => binary(u.clone(), Operator::Div, Primitive(crate::Primitive::Function((name.clone(), *func, vec![u.clone()])))),
+                    _ => return Err("Cannot differentiate this function"),
+                };
+                Ok(binary(outer, Operator::Mult, du))
+            }
+            ExpressionOperation((u, op, v)) => {
+                use Operator::*;
+                match op {
+                    Add | Sub => Ok(binary(u.derivative(var)?, *op, v.derivative(var)?)),
+                    Mult => {
+                        let left = binary(u.derivative(var)?, Mult, (**v).clone());
+                        Ok(binary(left, Add, binary((**u).clone(), Mult, v.derivative(var)?)))
+                    }
+                    Div => {
+                        let left = binary(u.derivative(var)?, Mult, (**v).clone());
+                        let numerator = binary(left, Sub, binary((**u).clone(), Mult, v.derivative(var)?));
+                        Ok(binary(numerator, Div, binary((**v).clone(), Pow, number(2.0))))
+                    }
+                    Pow => power(u, v, var),
+                    Eq | Ne | Lt | Le | Gt | Ge | And | Or => Ok(number(0.0)),
+                    Mod | IntDiv | BitAnd | BitOr | BitXor | Shl | Shr => Err("Cannot differentiate this operator"),
+                }
+            }
+            UnaryOperation((UnaryOperator::Neg, u)) => Ok(negate(u.derivative(var)?)),
+            UnaryOperation((UnaryOperator::Not, _)) => Ok(number(0.0)),
+            Conditional((condition, then, otherwise)) => {
+                let branches = (Box::new(then.derivative(var)?), Box::new(otherwise.derivative(var)?));
+                Ok(Conditional((condition.clone(), branches.0, branches.1)))
+            }
+            Piecewise((branches, otherwise)) => {
+                let branches = branches.iter().map(|(condition, branch)| Ok((condition.clone(), branch.derivative(var)?)));
+                let otherwise = match otherwise {
+                    Some(otherwise) => Some(Box::new(otherwise.derivative(var)?)),
+                    None => None,
+                };
+                Ok(Piecewise((branches.collect::<Result<_, &'static str>>()?, otherwise)))
+            }
+            _ => Err("Cannot differentiate this expression"),
+        }
+    }
+    // whether `var` appears in the expression
+    fn uses(&self, var: &str) -> bool {
+        match self {
+            Expression::Primitive(crate::Primitive::Identifier(ident)) => ident == var,
+            _ => self.children().into_iter().any(|child| child.uses(var)),
+        }
+    }
+}
+
+// `u ^ v`, by the power rule for an exponent that does not use `var` and as
+// `e ^ v * v'` for a power of `e`
+fn power(u: &Expression, v: &Expression, var: &str) -> Result<Expression, &'static str> {
+    if !v.uses(var) {
+        let exponent = match value(v) {
+            Some(n) => number(n - 1.0),
+            None => binary(v.clone(), Operator::Sub, number(1.0)),
+        };
+        let outer = binary(v.clone(), Operator::Mult, binary(u.clone(), Operator::Pow, exponent));
+        return Ok(binary(outer, Operator::Mult, u.derivative(var)?));
+    }
+    match u {
+        Expression::Primitive(Primitive::Identifier(ident)) if ident == "e" && ident != var => {
+            let outer = binary(u.clone(), Operator::Pow, v.clone());
+            Ok(binary(outer, Operator::Mult, v.derivative(var)?))
+        }
+        _ => Err("Cannot differentiate a power with a variable exponent"),
+    }
+}
+
+// the value of a number or a negated number
+fn value(expr: &Expression) -> Option<f64> {
+    match expr {
+        Expression::Primitive(Primitive::Number(num)) => Some(*num),
+        Expression::UnaryOperation((UnaryOperator::Neg, expr)) => value(expr).map(|num| -num),
+        _ => None,
+    }
+}
+
+// a value as the parser builds it, a negative number is a negation
+fn number(value: f64) -> Expression {
+    let literal = Expression::Primitive(Primitive::Number(value.abs()));
+    if value.is_sign_negative() {
+        Expression::UnaryOperation((UnaryOperator::Neg, Box::new(literal)))
+    } else {
+        literal
+    }
+}
+
+fn function(name: &str, func: fn(f64) -> f64, arg: &Expression) -> Expression {
+    Expression::Primitive(Primitive::Function((name.to_string(), Func::Unary(func), vec![arg.clone()])))
+}
+
+fn negate(expr: Expression) -> Expression {
+    match value(&expr) {
+        Some(num) => number(-num),
+        None => Expression::UnaryOperation((UnaryOperator::Neg, Box::new(expr))),
+    }
+}
+
+// `left op right` without the operands of `+ 0`, `- 0`, `* 0`, `* 1`, `/ 1` and `^ 1`
+fn binary(left: Expression, op: Operator, right: Expression) -> Expression {
+    use Operator::*;
+    match (value(&left), op, value(&right)) {
+        (Some(a), Add, Some(b)) => number(a + b),
+        (Some(a), Mult, Some(b)) => number(a * b),
+        (Some(0.0), Add, _) | (Some(1.0), Mult, _) => right,
+        (_, Add | Sub, Some(0.0)) | (_, Mult | Div | Pow, Some(1.0)) => left,
+        (Some(0.0), Sub, _) => negate(right),
+        (Some(0.0), Mult | Div, _) | (_, Mult, Some(0.0)) => number(0.0),
+        _ => Expression::ExpressionOperation((Box::new(left), op, Box::new(right))),
+    }
+}
+
+#[cfg(test)]
+mod derive_tests {
+    use crate::Moo;
+
+    fn derivative(moo: &Moo, source: &str) -> Result<String, &'static str> {
+        moo.parse(source).ok().unwrap().unwrap().derivative("x").map(|program| program.to_string())
+    }
+
+    #[test]
+    fn derivative_rules() {
+        let moo = Moo::new(|_| {});
+        assert_eq!(derivative(&moo, "x^2 * sin(x)"), Ok("2 * x * sin(x) + x ^ 2 * cos(x)".to_string()));
+        assert_eq!(derivative(&moo, "3 * x - y + 1"), Ok("3".to_string()));
+        assert_eq!(derivative(&moo, "1 / x"), Ok("-1 / x ^ 2".to_string()));
+        assert_eq!(derivative(&moo, "cos(2 * x)"), Ok("-sin(2 * x) * 2".to_string()));
+        assert_eq!(derivative(&moo, "e ^ (x ^ 3)"), Ok("e ^ x ^ 3 * (3 * x ^ 2)".to_string()));
+        assert_eq!(derivative(&moo, "x ^ x"), Err("Cannot differentiate a power with a variable exponent"));
+        assert_eq!(derivative(&moo, "x % 2"), Err("Cannot differentiate this operator"));
+        assert_eq!(derivative(&moo, "fact(x)"), Err("Cannot differentiate this function"));
+        // the values agree with a central difference
+        let program = moo.parse("sqrt(x) * |x - 3| / (1 + x ^ 2)").ok().unwrap().unwrap();
+        let derivative = program.derivative("x").unwrap();
+        for x in [0.5, 2.0, 4.5] {
+            let h = 1e-6;
+            let numeric = (program.run(x + h) - program.run(x - h)) / (2.0 * h);
+            assert!((derivative.run(x) - numeric).abs() < 1e-6, "{} at {}", derivative, x);
+        }
+    }
+
+    #[test]
+    fn derivative_branches() {
+        let moo = Moo::new(|_| {});
+        let piecewise = derivative(&moo, "{ x^2 if x < 0; sin(x) otherwise }");
+        assert_eq!(piecewise, Ok("{ 2 * x if x < 0; cos(x) otherwise }".to_string()));
+        assert_eq!(derivative(&moo, "x > 1 ? x ^ 3 : -x"), Ok("x > 1 ? 3 * x ^ 2 : -1".to_string()));
+        assert_eq!(derivative(&moo, "(x > 1) * x"), Ok("x > 1".to_string()));
+    }
+}
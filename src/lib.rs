@@ -1,15 +1,47 @@
 // order of operations
-// parentheses
+// parentheses and absolute value bars
+// factorial and superscript powers
+// square root
 // exponentiation
-// multiplication and division
+// negation
+// implicit multiplication (grouped mode)
+// multiplication, division, modulo and integer division
 // addition and subtraction
-// number | function
+// shift             (integer mode)
+// bitwise and       (integer mode)
+// bitwise xor       (integer mode)
+// bitwise or        (integer mode)
+// comparison
+// not
+// and
+// or
+// conditional
+// let
+// number | function | if | piecewise
+// the precedences are in `operators`, where an embedder can add operators
 
+mod analysis;
+mod canonical;
+pub mod cst;
+pub mod operators;
+mod compile;
+mod derive;
+mod print;
+mod rewrite;
+pub mod special;
+mod tokenize;
+mod trace;
 mod utils;
 
+pub use crate::compile::Compiled;
+pub use crate::operators::{Associativity, CustomOperator, Fixity, Operators};
+pub use crate::tokenize::{SpannedToken, TokenKind};
+pub use crate::trace::{Trace, TraceNode};
+
 use crate::utils::{IIter, Iter};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::f64;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 macro_rules! cast_opt_ok {
     ($i:expr) => {
@@ -23,8 +55,7 @@ macro_rules! cast_opt_ok {
 macro_rules! try_tokenize {
     ($self:ident,$start:ident,$i:ident,$j:ident) => {{
         let result = $i($self.source)?;
-        if result.is_some() {
-            let option = result.unwrap();
+        if let Some(option) = result {
             $self.shift(option.1);
             return Ok(Some((Token::$j(option.0), $start, $self.index)));
         }
@@ -40,87 +71,1028 @@ macro_rules! try_one_char {
     }};
 }
 
+macro_rules! try_str {
+    ($self:ident,$start:ident,$s:expr,$j:ident) => {{
+        if $self.source.starts_with($s) {
+            $self.shift($s.len());
+            return Ok(Some((Token::$j, $start, $self.index)));
+        }
+    }};
+}
+
 type Function = fn(f64) -> f64;
+type Function2 = fn(f64, f64) -> f64;
+type CheckedFunction = fn(f64) -> Result<f64, &'static str>;
+type CheckedFunction2 = fn(f64, f64) -> Result<f64, &'static str>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+pub enum Func {
+    Unary(Function),
+    Binary(Function2),
+    CheckedUnary(CheckedFunction),
+    CheckedBinary(CheckedFunction2),
+}
+
+impl Func {
+    fn arity(&self) -> usize {
+        match self {
+            Func::Unary(_) | Func::CheckedUnary(_) => 1,
+            Func::Binary(_) | Func::CheckedBinary(_) => 2,
+        }
+    }
+    fn call(&self, args: &[f64]) -> Result<f64, &'static str> {
+        match self {
+            Func::Unary(func) => Ok(func(args[0])),
+            Func::Binary(func) => Ok(func(args[0], args[1])),
+            Func::CheckedUnary(func) => func(args[0]),
+            Func::CheckedBinary(func) => func(args[0], args[1]),
+        }
+    }
+}
+
+/// Function table of a [`Moo`], the built-in functions, those of the `add_on`
+/// of [`Moo::new`] and the registered modules.
+#[derive(Default)]
+pub struct Functions<'a> {
+    map: HashMap<&'a str, Func>,
+}
+
+impl<'a> Functions<'a> {
+    pub fn insert(&mut self, name: &'a str, function: Function) {
+        self.map.insert(name, Func::Unary(function));
+    }
+    pub fn insert_binary(&mut self, name: &'a str, function: Function2) {
+        self.map.insert(name, Func::Binary(function));
+    }
+    /// Inserts a function that reports domain errors instead of returning `NaN`.
+    pub fn insert_checked(&mut self, name: &'a str, function: CheckedFunction) {
+        self.map.insert(name, Func::CheckedUnary(function));
+    }
+    pub fn insert_checked_binary(&mut self, name: &'a str, function: CheckedFunction2) {
+        self.map.insert(name, Func::CheckedBinary(function));
+    }
+    pub fn get(&self, name: &str) -> Option<&Func> {
+        self.map.get(name)
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+}
+
+/// Parser options, changed with [`Moo::configure`].
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Enables the bitwise operators `&`, `|`, `xor`, `<<` and `>>`.
+    pub integer_mode: bool,
+    /// `a == b` holds when `|a - b| <= tolerance * max(1, |a|, |b|)`.
+    pub tolerance: f64,
+    /// Deepest evaluation before it fails, counted in expression nodes being
+    /// evaluated at once together with those of the functions they call, so a
+    /// recursive call inside `n * f(n - 1)` takes three levels per call. This bounds
+    /// recursion such as `f(n) = n <= 1 ? 1 : n * f(n - 1)` and the stack it
+    /// uses, the default fits a 2 MiB thread stack.
+    pub max_call_depth: usize,
+    /// Reads `2x`, `3(x + 1)` and `2 sin(x)` as products, off by default.
+    pub implicit_multiplication: ImplicitMultiplication,
+    /// Longest source in bytes the parser accepts.
+    pub max_source_length: usize,
+    pub max_tokens: usize,
+    /// Deepest nesting of brackets, calls and operands such as `2^2^2` or
+    /// `--x`, every operator of a chain nests too as `1 + 2 + 3` is `(1 + 2) + 3`.
+    /// This keeps parsing and evaluating untrusted input from overflowing the
+    /// stack, the default fits a 2 MiB thread stack.
+    pub max_nesting_depth: usize,
+    /// Most expression nodes in a program, every number, name, call and operator is one.
+    pub max_nodes: usize,
+}
+
+/// How a juxtaposed operand such as the `x` of `2x` is multiplied, in every mode
+/// it takes exponentiation first (`2x^2 = 2 * x^2`) and it has to start with a
+/// name or `(`, a name followed by `(` is always a function call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImplicitMultiplication {
+    /// `2x` is an error.
+    Off,
+    /// Same precedence as `*`, `1/2x = (1/2) * x`.
+    AsProduct,
+    /// Binds tighter than `*` and `/`, `1/2x = 1 / (2 * x)`.
+    Grouped,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            integer_mode: false,
+            tolerance: 1e-9,
+            max_call_depth: 512,
+            implicit_multiplication: ImplicitMultiplication::Off,
+            max_source_length: 1 << 20,
+            max_tokens: 100_000,
+            max_nesting_depth: 64,
+            max_nodes: 10_000,
+        }
+    }
+}
+
+/// `f(t) = t^2; a = 2; f(a) + x`, a program is a list of function definitions
+/// and assignments followed by the expression it evaluates.
+///
+/// Statements are separated by `;` or by a newline outside of brackets, a line
+/// ending in an operator such as `x +` goes on on the next line. A program
+/// ending in an assignment evaluates to the assigned value.
+///
+/// Two programs are equal when their statements are, see [`Program::canonicalize`].
+#[derive(Debug, Clone)]
 pub struct Program {
+    pub definitions: Vec<Definition>,
+    /// Evaluated in order before [`Program::body`].
+    pub assignments: Vec<Assignment>,
     pub body: Expression,
+    config: Config,
+    spans: NodeSpans,
 }
 
-#[derive(Debug)]
+// source spans of the nodes of every definition, assignment and the body, in
+// the order the parser builds them, see `Program::spans`
+#[derive(Debug, Clone, Default)]
+struct NodeSpans {
+    definitions: Vec<Vec<Span>>,
+    assignments: Vec<Vec<Span>>,
+    body: Vec<Span>,
+}
+
+/// Where the nodes of a program are in the source, see [`Program::spans`].
+#[derive(Debug, Clone)]
+pub struct Spans<'p> {
+    // by node address, the program is borrowed so its nodes stay in place
+    by_node: HashMap<usize, Span>,
+    program: std::marker::PhantomData<&'p Program>,
+}
+
+impl Spans<'_> {
+    /// Where `node`, an expression of the program, is in the source.
+    pub fn get(&self, node: &Expression) -> Option<Span> {
+        self.by_node.get(&(node as *const Expression as usize)).copied()
+    }
+}
+
+/// `name(params) = body`, a function defined in the source text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Definition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expression,
+}
+
+/// `name = value`, visible to the statements after it but not inside
+/// function definitions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Expression,
+}
+
+/// Variables shared between programs, [`Program::eval`] reads `x`, `y` and
+/// the variables given to [`Moo::parse_in`] from it and stores every assignment.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    variables: HashMap<String, f64>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+    pub fn set(&mut self, name: &str, value: f64) {
+        self.variables.insert(name.to_string(), value);
+    }
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.variables.get(name).copied()
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.variables.contains_key(name)
+    }
+    // a copy an evaluation reads while it stores its assignments
+    fn copy_variables(&self) -> Vec<(String, f64)> {
+        self.variables.iter().map(|(name, value)| (name.clone(), *value)).collect()
+    }
+}
+
+/// Result of [`Program::eval`], every assignment in order and the final value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub assignments: Vec<(String, f64)>,
+    pub value: f64,
+}
+
+/// Why [`Program::eval_bounded`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundedError {
+    /// The program took more operations than its budget.
+    BudgetExceeded,
+    /// The cancellation flag was set.
+    Cancelled,
+    /// Evaluation failed as in [`Program::eval`].
+    Failed(&'static str),
+}
+
+impl BoundedError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            BoundedError::BudgetExceeded => "Evaluation budget exceeded",
+            BoundedError::Cancelled => "Evaluation cancelled",
+            BoundedError::Failed(message) => message,
+        }
+    }
+}
+
+impl std::fmt::Display for BoundedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for BoundedError {}
+
+/// The first failing node of [`Program::eval_checked`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    pub message: &'static str,
+    /// The operator, function or variable of the node, e.g. `/`, `sqrt` or `x`.
+    pub operation: String,
+    /// Where the node is in the source, `None` once the program is changed after parsing.
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalErrorKind {
+    /// `/`, `//` or `%` by zero.
+    DivisionByZero,
+    /// An operand outside the domain, e.g. `sqrt(-1)`, `(-1)!` or `(-8) ^ (1/3)`.
+    Domain,
+    /// An infinite value, e.g. `10 ^ 400` or a bit shifted out by `<<`.
+    Overflow,
+    /// A `NaN` given as a variable.
+    NaN,
+    /// Any other evaluation error, e.g. `No piecewise branch matched`.
+    Other,
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in '{}'", self.message, self.operation)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}..{}", span.char_start, span.char_end)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Comparisons and boolean operators give `1` for true and `0` for false,
+/// any value other than `0` and `NaN` counts as true.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expression {
     Primitive(Primitive),
-    ExpressionOperation((Box<Expression>, char, Box<Expression>)),
+    ExpressionOperation((Box<Expression>, Operator, Box<Expression>)),
+    UnaryOperation((UnaryOperator, Box<Expression>)),
+    /// An operator from [`Moo::add_operators`] and its one or two operands.
+    CustomOperation((CustomOperator, Vec<Expression>)),
+    /// `cond ? a : b` and `if(cond, a, b)`, only the taken branch is evaluated.
+    Conditional((Box<Expression>, Box<Expression>, Box<Expression>)),
+    /// `{ a if cond; b otherwise }`, (condition, expression) branches tried in
+    /// order and the optional `otherwise` branch.
+    Piecewise((Vec<(Expression, Expression)>, Option<Box<Expression>>)),
+    /// `let name = value in body`
+    Let((String, Box<Expression>, Box<Expression>)),
+    /// Stands in for source that did not parse, see [`Moo::parse_recovering`].
+    Error,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mult,
+    Div,
+    Pow,
+    /// Floored modulo, the result takes the sign of the divisor.
+    Mod,
+    /// Floored division, `a // b = floor(a / b)`.
+    IntDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Short-circuiting, the right side is skipped when the left side is false.
+    And,
+    /// Short-circuiting, the right side is skipped when the left side is true.
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+}
+
+/// Equality and hashing compare functions by their registered name.
+#[derive(Debug, Clone)]
 pub enum Primitive {
-    Function((Function, Box<Expression>)),
+    Function((String, Func, Vec<Expression>)),
+    /// Call of a function from [`Program::definitions`].
+    Call((String, Vec<Expression>)),
     Identifier(String),
     Number(f64),
 }
 
 impl Program {
-    fn run(&self, x: f64) -> f64 {
-        self.body.perform(x, 0.0)
-    }
-    fn runge_kutta(&self, x0: f64, y0: f64, step: f64) -> (f64, f64) {
-        let a1 = step * self.body.perform(x0, y0);
-        let a2 = step * self.body.perform(x0 + step / 2.0, y0 + a1 / 2.0);
-        let a3 = step * self.body.perform(x0 + step / 2.0, y0 + a2 / 2.0);
-        let a4 = step * self.body.perform(x0, y0 + a3);
+    /// Evaluates the program at `x`, a domain error gives `NaN`.
+    pub fn run(&self, x: f64) -> f64 {
+        self.try_run(x).unwrap_or(f64::NAN)
+    }
+    /// Evaluates the program at `x`, reporting domain errors such as `(-1)!`.
+    pub fn try_run(&self, x: f64) -> Result<f64, &'static str> {
+        self.evaluate(x, 0.0)
+    }
+    /// Runs the statements in order against `env`, assignments made before an
+    /// error are kept in `env`.
+    pub fn eval(&self, env: &mut Environment) -> Result<Evaluation, &'static str> {
+        let variables = env.copy_variables();
+        self.eval_in(env, &mut self.context(env, &variables))
+    }
+    /// Same as [`Program::eval`] but stops after `budget` operations or once
+    /// `cancel` is set, so a server can bound the time spent on untrusted input.
+    ///
+    /// Every expression node evaluated is one operation, a call counts the nodes
+    /// of the function body each time it runs. `cancel` is checked before every
+    /// operation and can be shared with another thread through an `Arc`.
+    pub fn eval_bounded(&self, env: &mut Environment, budget: u64, cancel: &AtomicBool) -> Result<Evaluation, BoundedError> {
+        let variables = env.copy_variables();
+        let mut ctx = self.context(env, &variables);
+        ctx.budget = budget;
+        ctx.cancel = Some(cancel);
+        self.eval_in(env, &mut ctx).map_err(|error| ctx.interrupted.unwrap_or(BoundedError::Failed(error)))
+    }
+    /// Same as [`Program::eval`] but a division by zero, a domain error, an
+    /// infinite value or a `NaN` variable is an error naming the node it happened at,
+    /// where [`Program::run`] would give `inf` or `NaN`.
+    pub fn eval_checked(&self, env: &mut Environment) -> Result<Evaluation, EvalError> {
+        let variables = env.copy_variables();
+        let mut ctx = self.context(env, &variables);
+        ctx.checked = true;
+        self.eval_in(env, &mut ctx).map_err(|message| match ctx.fault {
+            Some((node, kind, message)) => EvalError { kind, message, operation: node.operation(), span: self.spans().get(node) },
+            None => EvalError { kind: EvalErrorKind::Other, message, operation: String::new(), span: None },
+        })
+    }
+    // `x`, `y` and the variables of `env` in scope
+    fn context<'p>(&'p self, env: &Environment, variables: &'p [(String, f64)]) -> Context<'p> {
+        let mut ctx = Context::new(self, env.get("x").unwrap_or(0.0), env.get("y").unwrap_or(0.0));
+        ctx.locals.extend(variables.iter().map(|(name, value)| (name.as_str(), *value)));
+        ctx
+    }
+    fn eval_in<'p>(&'p self, env: &mut Environment, ctx: &mut Context<'p>) -> Result<Evaluation, &'static str> {
+        let mut assignments = Vec::new();
+        for assignment in self.assignments.iter() {
+            let value = assignment.value.perform(ctx)?;
+            ctx.locals.push((&assignment.name, value));
+            env.set(&assignment.name, value);
+            assignments.push((assignment.name.clone(), value));
+        }
+        let value = self.body.perform(ctx)?;
+        Ok(Evaluation { assignments, value })
+    }
+    /// Where the nodes of this program are in the source, looked up once for
+    /// any number of nodes.
+    ///
+    /// The spans are lost once the program is changed in any way after parsing,
+    /// through its fields as well as its methods, and a program built by hand
+    /// has none.
+    pub fn spans(&self) -> Spans<'_> {
+        let mut by_node = HashMap::new();
+        let definitions = self.definitions.iter().map(|d| &d.body).zip(self.spans.definitions.iter());
+        let assignments = self.assignments.iter().map(|a| &a.value).zip(self.spans.assignments.iter());
+        for (root, root_spans) in definitions.chain(assignments).chain([(&self.body, &self.spans.body)]) {
+            let mut nodes = Vec::new();
+            root.post_order(&mut nodes);
+            // a tree changed after parsing no longer matches its spans
+            if nodes.len() == root_spans.len() {
+                by_node.extend(nodes.into_iter().map(|node| node as *const Expression as usize).zip(root_spans.iter().copied()));
+            }
+        }
+        Spans { by_node, program: std::marker::PhantomData }
+    }
+    fn evaluate(&self, x: f64, y: f64) -> Result<f64, &'static str> {
+        let mut ctx = Context::new(self, x, y);
+        self.assign(&self.assignments, &mut ctx)?;
+        self.body.perform(&mut ctx)
+    }
+    fn assign<'p>(&self, assignments: &'p [Assignment], ctx: &mut Context<'p>) -> Result<(), &'static str> {
+        for assignment in assignments.iter() {
+            let value = assignment.value.perform(ctx)?;
+            ctx.locals.push((&assignment.name, value));
+        }
+        Ok(())
+    }
+    /// Samples `x` over `from..=to` and reports every piecewise expression whose
+    /// branches overlap or leave a gap, consecutive samples are merged into one range.
+    ///
+    /// Definitions with a single parameter are sampled over that parameter instead.
+    pub fn piecewise_warnings(&self, from: f64, to: f64, samples: usize) -> Vec<PiecewiseWarning> {
+        let mut warnings = Vec::new();
+        for (i, assignment) in self.assignments.iter().enumerate() {
+            warnings.append(&mut self.sample_piecewise(&assignment.value, Some(&self.assignments[..i]), None, from, to, samples));
+        }
+        warnings.append(&mut self.sample_piecewise(&self.body, Some(&self.assignments), None, from, to, samples));
+        for definition in self.definitions.iter().filter(|d| d.params.len() == 1) {
+            warnings.append(&mut self.sample_piecewise(&definition.body, None, Some(&definition.params[0]), from, to, samples));
+        }
+        warnings
+    }
+    // `assignments` run before every sample, definitions see only `param`
+    fn sample_piecewise(&self, expr: &Expression, assignments: Option<&[Assignment]>, param: Option<&str>, from: f64, to: f64, samples: usize) -> Vec<PiecewiseWarning> {
+        let mut warnings = Vec::new();
+        expr.visit_piecewise(&mut |branches, otherwise| {
+            let mut open: Vec<PiecewiseWarning> = Vec::new();
+            for i in 0..samples.max(2) {
+                let x = from + (to - from) * i as f64 / (samples.max(2) - 1) as f64;
+                let mut ctx = Context::new(self, x, 0.0);
+                if let Some(assignments) = assignments {
+                    if self.assign(assignments, &mut ctx).is_err() {
+                        continue;
+                    }
+                }
+                if let Some(param) = param {
+                    ctx.locals.push((param, x));
+                }
+                let holds: Vec<usize> = (0..branches.len())
+                    .filter(|&k| branches[k].0.perform(&mut ctx).map(truth).unwrap_or(false))
+                    .collect();
+                let mut current = Vec::new();
+                if holds.len() > 1 {
+                    current.push(PiecewiseWarning::Overlap { branches: (holds[0], holds[1]), from: x, to: x });
+                }
+                if holds.is_empty() && !otherwise {
+                    current.push(PiecewiseWarning::Gap { from: x, to: x });
+                }
+                // extend the ranges still running, close the others
+                let mut next = Vec::new();
+                for warning in current {
+                    let extended = open.iter().position(|w| w.same_kind(&warning));
+                    match extended {
+                        Some(k) => next.push(open.remove(k).extend(x)),
+                        None => next.push(warning),
+                    }
+                }
+                warnings.append(&mut open);
+                open = next;
+            }
+            warnings.append(&mut open);
+        });
+        warnings
+    }
+    pub fn runge_kutta(&self, x0: f64, y0: f64, step: f64) -> (f64, f64) {
+        let f = |x, y| self.evaluate(x, y).unwrap_or(f64::NAN);
+        let a1 = step * f(x0, y0);
+        let a2 = step * f(x0 + step / 2.0, y0 + a1 / 2.0);
+        let a3 = step * f(x0 + step / 2.0, y0 + a2 / 2.0);
+        let a4 = step * f(x0, y0 + a3);
         (y0 + (a1 + 2.0 * a2 + 2.0 * a3 + a4) / 6.0, x0 + step)
     }
 }
 
+impl PiecewiseWarning {
+    fn same_kind(&self, other: &PiecewiseWarning) -> bool {
+        use PiecewiseWarning::*;
+        match (self, other) {
+            (Overlap { branches: a, .. }, Overlap { branches: b, .. }) => a == b,
+            (Gap { .. }, Gap { .. }) => true,
+            _ => false,
+        }
+    }
+    fn extend(self, x: f64) -> PiecewiseWarning {
+        use PiecewiseWarning::*;
+        match self {
+            Overlap { branches, from, .. } => Overlap { branches, from, to: x },
+            Gap { from, .. } => Gap { from, to: x },
+        }
+    }
+}
+
 impl Primitive {
+    #[allow(dead_code)]
     fn typ(&self) -> &'static str {
         use Primitive::*;
         match self {
             Function(_) => "Function",
+            Call(_) => "Call",
             Identifier(_) => "Identifier",
             Number(_) => "Number",
         }
     }
-    fn perform(&self, x: f64, y: f64) -> f64 {
+    fn perform<'p>(&'p self, ctx: &mut Context<'p>) -> Result<f64, &'static str> {
         use Primitive::*;
         match self {
             Function(func) => {
-                (func.0)(func.1.perform(x, y))
+                let args = func.2.iter().map(|arg| arg.perform(ctx)).collect::<Result<Vec<f64>, _>>()?;
+                func.1.call(&args)
+            }
+            Call(call) => {
+                let args = call.1.iter().map(|arg| arg.perform(ctx)).collect::<Result<Vec<f64>, _>>()?;
+                let definition = ctx.definitions.iter().find(|d| d.name == call.0).ok_or("Unknown function")?;
+                // a call only sees its own parameters, not the caller's locals
+                let params = definition.params.iter().map(|p| p.as_str()).zip(args).collect();
+                let caller = std::mem::replace(&mut ctx.locals, params);
+                let result = definition.body.perform(ctx);
+                ctx.locals = caller;
+                result
             }
             Identifier(ident) => {
+                if let Some((_, value)) = ctx.locals.iter().rev().find(|(name, _)| name == ident) {
+                    return Ok(*value);
+                }
                 match ident.as_str() {
-                    "x" => x,
-                    "y" => y,
-                    _ => 0.0
+                    "x" => Ok(ctx.x),
+                    "y" => Ok(ctx.y),
+                    _ => constant(ident).ok_or("Unknown variable")
                 }
             }
-            Number(num) => *num
+            Number(num) => Ok(*num)
+        }
+    }
+}
+
+// evaluation state handed down the tree
+struct Context<'p> {
+    x: f64,
+    y: f64,
+    config: Config,
+    definitions: &'p [Definition],
+    // parameters and let bindings in scope, innermost last
+    locals: Vec<(&'p str, f64)>,
+    // nodes being evaluated, across calls
+    depth: usize,
+    // operations left and the flag of `Program::eval_bounded`
+    budget: u64,
+    cancel: Option<&'p AtomicBool>,
+    interrupted: Option<BoundedError>,
+    // `Program::eval_checked`, the innermost node that failed
+    checked: bool,
+    fault: Option<(&'p Expression, EvalErrorKind, &'static str)>,
+    // `Program::trace`, the nodes evaluated whose parent is not done yet
+    trace: Option<Vec<trace::Traced<'p>>>,
+}
+
+impl<'p> Context<'p> {
+    fn new(program: &'p Program, x: f64, y: f64) -> Context<'p> {
+        Context::with(program.config, &program.definitions, x, y)
+    }
+    fn with(config: Config, definitions: &'p [Definition], x: f64, y: f64) -> Context<'p> {
+        Context {
+            x,
+            y,
+            config,
+            definitions,
+            locals: Vec::new(),
+            depth: 0,
+            budget: u64::MAX,
+            cancel: None,
+            interrupted: None,
+            checked: false,
+            fault: None,
+            trace: None,
+        }
+    }
+    // takes one operation from the budget
+    fn step(&mut self) -> Result<(), &'static str> {
+        if self.budget == 0 {
+            self.interrupted = Some(BoundedError::BudgetExceeded);
+        } else if self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            self.interrupted = Some(BoundedError::Cancelled);
+        } else {
+            self.budget -= 1;
+            return Ok(());
+        }
+        Err("Evaluation interrupted")
+    }
+    // an error or a value that is not finite fails the node in checked mode
+    fn check(&mut self, node: &'p Expression, value: Result<f64, &'static str>) -> Result<f64, &'static str> {
+        match value {
+            Ok(value) if value.is_finite() => Ok(value),
+            Ok(value) => {
+                let (kind, message) = node.non_finite(value);
+                Err(self.fail(node, kind, message))
+            }
+            Err(message) => Err(self.fail(node, node.error_kind(message), message)),
+        }
+    }
+    // only the first failure is kept, the nodes around it fail because of it
+    fn fail(&mut self, node: &'p Expression, kind: EvalErrorKind, message: &'static str) -> &'static str {
+        if self.fault.is_none() {
+            self.fault = Some((node, kind, message));
         }
+        message
+    }
+}
+
+// `pi` and `e`, a variable of the same name shadows them
+fn constant(name: &str) -> Option<f64> {
+    match name {
+        "pi" => Some(f64::consts::PI),
+        "e" => Some(f64::consts::E),
+        _ => None,
+    }
+}
+
+fn truth(value: f64) -> bool {
+    value != 0.0 && !value.is_nan()
+}
+
+fn boolean(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
     }
 }
 
 impl Expression {
-    fn perform(&self, x: f64, y: f64) -> f64 {
+    fn perform<'p>(&'p self, ctx: &mut Context<'p>) -> Result<f64, &'static str> {
+        ctx.step()?;
+        if ctx.depth >= ctx.config.max_call_depth {
+            return Err("Recursion depth exceeded");
+        }
+        ctx.depth += 1;
+        let value = if ctx.checked || ctx.trace.is_some() { self.perform_observed(ctx) } else { self.value(ctx) };
+        ctx.depth -= 1;
+        value
+    }
+    // checked and traced evaluation, apart so the plain one keeps a small stack frame
+    #[inline(never)]
+    fn perform_observed<'p>(&'p self, ctx: &mut Context<'p>) -> Result<f64, &'static str> {
+        let traced = ctx.trace.as_ref().map(Vec::len);
+        let mut value = self.value(ctx);
+        if ctx.checked {
+            value = ctx.check(self, value);
+        }
+        if let Some(mark) = traced {
+            ctx.record(self, mark, value);
+        }
+        value
+    }
+    fn value<'p>(&'p self, ctx: &mut Context<'p>) -> Result<f64, &'static str> {
         use Expression::*;
         match self {
             Primitive(primitive) => {
-                primitive.perform(x, y)
+                primitive.perform(ctx)
             }
             ExpressionOperation(expr) => {
+                let left = expr.0.perform(ctx)?;
                 match expr.1 {
-                    '+' => expr.0.perform(x, y) + expr.2.perform(x, y),
-                    '-' => expr.0.perform(x, y) - expr.2.perform(x, y),
-                    '*' => expr.0.perform(x, y) * expr.2.perform(x, y),
-                    '/' => expr.0.perform(x, y) / expr.2.perform(x, y),
-                    '^' => f64::powf(expr.0.perform(x, y), expr.2.perform(x, y)),
-                    _ => 0.0
+                    Operator::And if !truth(left) => return Ok(0.0),
+                    Operator::Or if truth(left) => return Ok(1.0),
+                    _ => {}
+                }
+                let right = expr.2.perform(ctx)?;
+                if ctx.checked && right == 0.0 && matches!(expr.1, Operator::Div | Operator::IntDiv | Operator::Mod) {
+                    return Err(ctx.fail(self, EvalErrorKind::DivisionByZero, "Division by zero"));
+                }
+                expr.1.perform(left, right, &ctx.config)
+            }
+            UnaryOperation(expr) => {
+                let value = expr.1.perform(ctx)?;
+                Ok(match expr.0 {
+                    UnaryOperator::Neg => -value,
+                    UnaryOperator::Not => boolean(!truth(value)),
+                })
+            }
+            CustomOperation(expr) => {
+                let args = expr.1.iter().map(|arg| arg.perform(ctx)).collect::<Result<Vec<f64>, _>>()?;
+                expr.0.function.call(&args)
+            }
+            Conditional(expr) => {
+                if truth(expr.0.perform(ctx)?) {
+                    expr.1.perform(ctx)
+                } else {
+                    expr.2.perform(ctx)
+                }
+            }
+            Piecewise(expr) => {
+                for (condition, branch) in expr.0.iter() {
+                    if truth(condition.perform(ctx)?) {
+                        return branch.perform(ctx);
+                    }
+                }
+                match &expr.1 {
+                    Some(otherwise) => otherwise.perform(ctx),
+                    None => Err("No piecewise branch matched"),
+                }
+            }
+            Let(expr) => {
+                let value = expr.1.perform(ctx)?;
+                ctx.locals.push((&expr.0, value));
+                let result = expr.2.perform(ctx);
+                ctx.locals.pop();
+                result
+            }
+            Error => Err("Program has syntax errors"),
+        }
+    }
+    // operands are finite in checked mode, so a value that is not is this node's fault
+    fn non_finite(&self, value: f64) -> (EvalErrorKind, &'static str) {
+        use Expression::*;
+        match self {
+            _ if value.is_infinite() => (EvalErrorKind::Overflow, "Overflow"),
+            Primitive(crate::Primitive::Function(_)) | CustomOperation(_) | ExpressionOperation((_, Operator::Pow, _)) => {
+                (EvalErrorKind::Domain, "Domain error")
+            }
+            _ => (EvalErrorKind::NaN, "Not a number"),
+        }
+    }
+    fn error_kind(&self, message: &'static str) -> EvalErrorKind {
+        use Expression::*;
+        match self {
+            _ if message == "Shift overflow" => EvalErrorKind::Overflow,
+            Primitive(crate::Primitive::Function(_)) | CustomOperation(_) | ExpressionOperation(_) => EvalErrorKind::Domain,
+            _ => EvalErrorKind::Other,
+        }
+    }
+    // what `EvalError` names the node by
+    fn operation(&self) -> String {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Function(func)) => func.0.clone(),
+            Primitive(crate::Primitive::Call(call)) => call.0.clone(),
+            Primitive(crate::Primitive::Identifier(ident)) => ident.clone(),
+            Primitive(crate::Primitive::Number(num)) => num.to_string(),
+            ExpressionOperation(expr) => expr.1.symbol().to_string(),
+            UnaryOperation((UnaryOperator::Neg, _)) => "-".to_string(),
+            UnaryOperation((UnaryOperator::Not, _)) => "not".to_string(),
+            CustomOperation(expr) => expr.0.symbol.clone(),
+            Conditional(_) => "?".to_string(),
+            Piecewise(_) => "piecewise".to_string(),
+            Let(_) => "let".to_string(),
+            Error => "<error>".to_string(),
+        }
+    }
+    // the operands in the order the parser reads them, a piecewise branch before its condition
+    fn children(&self) -> Vec<&Expression> {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Function(func)) => func.2.iter().collect(),
+            Primitive(crate::Primitive::Call(call)) => call.1.iter().collect(),
+            Primitive(_) | Error => Vec::new(),
+            ExpressionOperation(expr) => vec![&expr.0, &expr.2],
+            UnaryOperation(expr) => vec![&expr.1],
+            CustomOperation(expr) => expr.1.iter().collect(),
+            Conditional(expr) => vec![&expr.0, &expr.1, &expr.2],
+            Piecewise(expr) => {
+                let branches = expr.0.iter().flat_map(|(condition, branch)| [branch, condition]);
+                branches.chain(expr.1.as_deref()).collect()
+            }
+            Let(expr) => vec![&expr.1, &expr.2],
+        }
+    }
+    fn children_mut(&mut self) -> Vec<&mut Expression> {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Function(func)) => func.2.iter_mut().collect(),
+            Primitive(crate::Primitive::Call(call)) => call.1.iter_mut().collect(),
+            Primitive(_) | Error => Vec::new(),
+            ExpressionOperation(expr) => vec![&mut *expr.0, &mut *expr.2],
+            UnaryOperation(expr) => vec![&mut *expr.1],
+            CustomOperation(expr) => expr.1.iter_mut().collect(),
+            Conditional(expr) => vec![&mut *expr.0, &mut *expr.1, &mut *expr.2],
+            Piecewise(expr) => {
+                let branches = expr.0.iter_mut().flat_map(|(condition, branch)| [branch, condition]);
+                branches.chain(expr.1.as_deref_mut()).collect()
+            }
+            Let(expr) => vec![&mut *expr.1, &mut *expr.2],
+        }
+    }
+    // every node of the tree, children before their parent as the parser builds them
+    fn post_order<'e>(&'e self, nodes: &mut Vec<&'e Expression>) {
+        for child in self.children() {
+            child.post_order(nodes);
+        }
+        nodes.push(self);
+    }
+    fn visit_piecewise(&self, f: &mut impl FnMut(&Vec<(Expression, Expression)>, bool)) {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Function(func)) => func.2.iter().for_each(|arg| arg.visit_piecewise(f)),
+            Primitive(crate::Primitive::Call(call)) => call.1.iter().for_each(|arg| arg.visit_piecewise(f)),
+            Primitive(_) => {}
+            ExpressionOperation(expr) => {
+                expr.0.visit_piecewise(f);
+                expr.2.visit_piecewise(f);
+            }
+            UnaryOperation(expr) => expr.1.visit_piecewise(f),
+            CustomOperation(expr) => expr.1.iter().for_each(|arg| arg.visit_piecewise(f)),
+            Conditional(expr) => {
+                expr.0.visit_piecewise(f);
+                expr.1.visit_piecewise(f);
+                expr.2.visit_piecewise(f);
+            }
+            Piecewise(expr) => {
+                f(&expr.0, expr.1.is_some());
+                for (condition, branch) in expr.0.iter() {
+                    condition.visit_piecewise(f);
+                    branch.visit_piecewise(f);
+                }
+                if let Some(otherwise) = &expr.1 {
+                    otherwise.visit_piecewise(f);
+                }
+            }
+            Let(expr) => {
+                expr.1.visit_piecewise(f);
+                expr.2.visit_piecewise(f);
+            }
+            Error => {}
+        }
+    }
+    // checks that every variable is bound and every call names a definition
+    // with the right number of arguments
+    fn resolve<'p>(&'p self, scope: &mut Vec<&'p str>, definitions: &[Definition], current: Option<(&str, usize)>) -> Result<(), &'static str> {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Function(func)) => {
+                func.2.iter().try_for_each(|arg| arg.resolve(scope, definitions, current))
+            }
+            Primitive(crate::Primitive::Call(call)) => {
+                let arity = match definitions.iter().find(|d| d.name == call.0) {
+                    Some(definition) => definition.params.len(),
+                    None => match current {
+                        Some((name, arity)) if name == call.0 => arity,
+                        _ => return Err("Unknown function"),
+                    },
+                };
+                if call.1.len() != arity {
+                    return Err("Function called with wrong number of arguments");
+                }
+                call.1.iter().try_for_each(|arg| arg.resolve(scope, definitions, current))
+            }
+            Primitive(crate::Primitive::Identifier(ident)) => {
+                if scope.contains(&ident.as_str()) || ident == "x" || ident == "y" || constant(ident).is_some() {
+                    Ok(())
+                } else {
+                    Err("Unknown variable")
+                }
+            }
+            Primitive(crate::Primitive::Number(_)) => Ok(()),
+            ExpressionOperation(expr) => {
+                expr.0.resolve(scope, definitions, current)?;
+                expr.2.resolve(scope, definitions, current)
+            }
+            UnaryOperation(expr) => expr.1.resolve(scope, definitions, current),
+            CustomOperation(expr) => expr.1.iter().try_for_each(|arg| arg.resolve(scope, definitions, current)),
+            Conditional(expr) => {
+                expr.0.resolve(scope, definitions, current)?;
+                expr.1.resolve(scope, definitions, current)?;
+                expr.2.resolve(scope, definitions, current)
+            }
+            Piecewise(expr) => {
+                for (condition, branch) in expr.0.iter() {
+                    condition.resolve(scope, definitions, current)?;
+                    branch.resolve(scope, definitions, current)?;
+                }
+                match &expr.1 {
+                    Some(otherwise) => otherwise.resolve(scope, definitions, current),
+                    None => Ok(()),
+                }
+            }
+            Let(expr) => {
+                expr.1.resolve(scope, definitions, current)?;
+                scope.push(&expr.0);
+                let result = expr.2.resolve(scope, definitions, current);
+                scope.pop();
+                result
+            }
+            Error => Ok(()),
+        }
+    }
+    // identifiers not bound by a `let` of this tree or by `scope`
+    fn free_identifiers<'e>(&'e self, scope: &mut Vec<&'e str>, free: &mut BTreeSet<&'e str>) {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Identifier(ident)) => {
+                if !scope.contains(&ident.as_str()) {
+                    free.insert(ident);
+                }
+            }
+            Let(expr) => {
+                expr.1.free_identifiers(scope, free);
+                scope.push(&expr.0);
+                expr.2.free_identifiers(scope, free);
+                scope.pop();
+            }
+            _ => self.children().into_iter().for_each(|child| child.free_identifiers(scope, free)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PiecewiseWarning {
+    /// Branches `.0` and `.1` both hold for every sample of `from..=to`.
+    Overlap { branches: (usize, usize), from: f64, to: f64 },
+    /// No branch holds and there is no `otherwise` for every sample of `from..=to`.
+    Gap { from: f64, to: f64 },
+}
+
+impl Operator {
+    fn is_bitwise(&self) -> bool {
+        use Operator::*;
+        matches!(self, BitAnd | BitOr | BitXor | Shl | Shr)
+    }
+    fn perform(&self, left: f64, right: f64, config: &Config) -> Result<f64, &'static str> {
+        use Operator::*;
+        let equal = || (left - right).abs() <= config.tolerance * left.abs().max(right.abs()).max(1.0);
+        match self {
+            Add => Ok(left + right),
+            Sub => Ok(left - right),
+            Mult => Ok(left * right),
+            Div => Ok(left / right),
+            Pow => Ok(f64::powf(left, right)),
+            Mod => Ok(floored_mod(left, right)),
+            IntDiv => Ok(floored_div(left, right)),
+            BitAnd => Ok((bitwise_operand(left)? & bitwise_operand(right)?) as f64),
+            BitOr => Ok((bitwise_operand(left)? | bitwise_operand(right)?) as f64),
+            BitXor => Ok((bitwise_operand(left)? ^ bitwise_operand(right)?) as f64),
+            Shl => {
+                let (value, shift) = (bitwise_operand(left)?, shift_amount(right)?);
+                let result = value << shift;
+                if result >> shift != value {
+                    return Err("Shift overflow");
                 }
+                Ok(result as f64)
             }
+            Shr => Ok((bitwise_operand(left)? >> shift_amount(right)?) as f64),
+            Eq => Ok(boolean(equal())),
+            Ne => Ok(boolean(!equal())),
+            Lt => Ok(boolean(left < right && !equal())),
+            Le => Ok(boolean(left < right || equal())),
+            Gt => Ok(boolean(left > right && !equal())),
+            Ge => Ok(boolean(left > right || equal())),
+            And | Or => Ok(boolean(truth(right))),
         }
     }
 }
 
+// fmod is exact, only the sign needs to follow the divisor
+fn floored_mod(a: f64, b: f64) -> f64 {
+    let r = a % b;
+    if r != 0.0 && (r < 0.0) != (b < 0.0) {
+        r + b
+    } else {
+        r
+    }
+}
+
+// same steps as python's float floor division, so `a == b * (a // b) + a % b`
+fn floored_div(a: f64, b: f64) -> f64 {
+    if b == 0.0 {
+        return a / b;
+    }
+    let r = a % b;
+    let mut div = (a - r) / b;
+    if r != 0.0 && (r < 0.0) != (b < 0.0) {
+        div -= 1.0;
+    }
+    let floor = div.floor();
+    if div - floor > 0.5 {
+        floor + 1.0
+    } else {
+        floor
+    }
+}
+
+// two's complement operands, exact up to 2^53
+fn bitwise_operand(value: f64) -> Result<i64, &'static str> {
+    if value != value.floor() || value.abs() >= i64::MAX as f64 {
+        return Err("Bitwise operand must be an integer");
+    }
+    Ok(value as i64)
+}
+
+fn shift_amount(value: f64) -> Result<u32, &'static str> {
+    let shift = bitwise_operand(value)?;
+    if !(0..64).contains(&shift) {
+        return Err("Shift amount out of range");
+    }
+    Ok(shift as u32)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Identifier(String),
     Number(f64),
@@ -129,72 +1101,220 @@ pub enum Token {
     Mult,
     Div,
     Pow,
+    Mod,
+    IntDiv,
+    BitAnd,
+    BitOr,
+    Xor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Not,
+    If,
+    Otherwise,
+    Let,
+    In,
+    Assign,
+    Question,
+    Colon,
+    Fact,
+    DoubleFact,
     LParent,
     RParent,
+    LBrace,
+    RBrace,
     Comma,
+    Semicolon,
+    Newline,
+    /// `√`, square root of the operand that follows.
+    Sqrt,
+    /// `x²`, a run of superscript digits.
+    Superscript(f64),
+    /// `|` opening an absolute value, told apart from [`Token::BitOr`] before parsing.
+    AbsOpen,
+    AbsClose,
+    /// A symbol from [`Moo::add_operators`].
+    Symbol(String),
+    /// A character no token starts with.
+    Unknown(char),
 }
 
 impl Token {
+    // an operator or keyword an expression cannot end with, `|` and custom
+    // symbols are left out as they can close an absolute value or be postfix
+    fn continues(&self) -> bool {
+        use Token::*;
+        matches!(
+            self,
+            Add | Sub | Mult | Div | Pow | Mod | IntDiv | BitAnd | Xor | Shl | Shr | Eq | Ne | Lt | Le | Gt | Ge | And | Or | Not
+                | Let | In | Assign | Question | Colon | Comma | Sqrt
+        )
+    }
+    #[allow(dead_code)]
     fn typ(&self) -> &'static str {
         use Token::*;
         match self {
             Identifier(_) => "Identifier",
             Number(_) => "Number",
-            Add | Sub | Mult | Div | Pow => "Operator",
-            LParent => "LParent",
-            RParent => "RParent",
+            Add | Sub | Mult | Div | Pow | Mod | IntDiv => "Operator",
+            BitAnd | BitOr | Xor | Shl | Shr | Fact | DoubleFact => "Operator",
+            Eq | Ne | Lt | Le | Gt | Ge | And | Or | Not | Question | Colon => "Operator",
+            If | Otherwise | Let | In => "Keyword",
+            Assign => "Operator",
+            LParent | RParent => "Parenthesis",
+            LBrace | RBrace => "Brace",
+            AbsOpen | AbsClose => "Bar",
+            Unknown(_) => "Unknown",
             Comma => "Comma",
+            Semicolon => "Semicolon",
+            Newline => "Newline",
+            Sqrt | Superscript(_) | Symbol(_) => "Operator",
         }
     }
-    fn to_string(&self) -> String {
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Token::*;
         match self {
-            Number(num) => num.to_string(),
-            Add => "+".to_string(),
-            Sub => "-".to_string(),
-            Mult => "*".to_string(),
-            Div => "/".to_string(),
-            Pow => "^".to_string(),
-            LParent => "(".to_string(),
-            RParent => ")".to_string(),
-            Comma => "Comma".to_string(),
-            Identifier(ident) => ident.to_string(),
+            Number(num) => write!(f, "{}", num),
+            Add => write!(f, "+"),
+            Sub => write!(f, "-"),
+            Mult => write!(f, "*"),
+            Div => write!(f, "/"),
+            Pow => write!(f, "^"),
+            Mod => write!(f, "%"),
+            IntDiv => write!(f, "//"),
+            BitAnd => write!(f, "&"),
+            BitOr | AbsOpen | AbsClose => write!(f, "|"),
+            Xor => write!(f, "xor"),
+            Shl => write!(f, "<<"),
+            Shr => write!(f, ">>"),
+            Eq => write!(f, "=="),
+            Ne => write!(f, "!="),
+            Lt => write!(f, "<"),
+            Le => write!(f, "<="),
+            Gt => write!(f, ">"),
+            Ge => write!(f, ">="),
+            And => write!(f, "and"),
+            Or => write!(f, "or"),
+            Not => write!(f, "not"),
+            If => write!(f, "if"),
+            Otherwise => write!(f, "otherwise"),
+            Let => write!(f, "let"),
+            In => write!(f, "in"),
+            Assign => write!(f, "="),
+            Question => write!(f, "?"),
+            Colon => write!(f, ":"),
+            Fact => write!(f, "!"),
+            DoubleFact => write!(f, "!!"),
+            LParent => write!(f, "("),
+            RParent => write!(f, ")"),
+            LBrace => write!(f, "{{"),
+            RBrace => write!(f, "}}"),
+            Comma => write!(f, "Comma"),
+            Semicolon => write!(f, ";"),
+            Newline => writeln!(f),
+            Sqrt => write!(f, "√"),
+            Superscript(num) => {
+                const DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+                for digit in num.to_string().chars() {
+                    write!(f, "{}", digit.to_digit(10).map_or(digit, |d| DIGITS[d as usize]))?;
+                }
+                Ok(())
+            }
+            Identifier(ident) | Symbol(ident) => write!(f, "{}", ident),
+            Unknown(ch) => write!(f, "{}", ch),
         }
     }
 }
 
+/// Where a token sits in the source, `start..end` in bytes for slicing and
+/// `char_start..char_end` in chars for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+}
+
+/// An error and the part of the source it points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MooError {
+    pub message: &'static str,
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for MooError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}..{}", self.message, span.char_start, span.char_end),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for MooError {}
+
+impl PartialEq<&str> for MooError {
+    fn eq(&self, other: &&str) -> bool {
+        self.message == *other
+    }
+}
+
 struct Tokenizer<'a> {
+    // byte offset of `source` in the text
     index: usize,
+    // char offset of `source` in the text
+    chars: usize,
     source: &'a str,
+    // open parentheses and braces, newlines inside them are whitespace
+    depth: usize,
+    // the last token needs an operand after it, so a newline is whitespace
+    continued: bool,
+    // the first line break between two tokens inside brackets, where recovery
+    // ends the statement when the bracket is never closed
+    breaks: Vec<Span>,
+    // custom operator symbols, longest first
+    symbols: &'a [String],
 }
 
-fn takes(source: &str, f: fn(ch: char) -> bool) -> Result<(&str, usize), &str> {
+// lengths are in bytes so they can slice `source`
+fn takes(source: &str, f: fn(ch: char) -> bool) -> Result<(&str, usize), &'static str> {
     let mut i: usize = 0;
     for ch in source.chars() {
         if f(ch) {
-            i += 1;
+            i += ch.len_utf8();
         } else {
             break;
         }
     }
     if i == 0 {
-        Err("No Matches".into())
+        Err("No Matches")
     } else {
         Ok((&source[..i], i))
     }
 }
 
-fn identifier(source: &str) -> Result<Option<(String, usize)>, &str> {
+fn identifier(source: &str) -> Result<Option<(String, usize)>, &'static str> {
     let mut i: usize = 0;
     let mut chars = source.chars();
     let ch = cast_opt_ok!(chars.next());
-    if !(ch.is_alphabetic() || ch == '_') {
+    if !(ch.is_alphabetic() || ch == '_') || symbol(ch).is_some() {
         return Ok(None);
     }
-    i += 1;
+    i += ch.len_utf8();
     for ch in chars {
-        if ch.is_alphanumeric() || ch == '_' {
-            i += 1;
+        if (ch.is_alphanumeric() || ch == '_') && symbol(ch).is_none() {
+            i += ch.len_utf8();
         } else {
             break;
         }
@@ -206,320 +1326,1325 @@ fn identifier(source: &str) -> Result<Option<(String, usize)>, &str> {
     }
 }
 
-fn number(source: &str) -> Result<Option<(f64, usize)>, &str> {
-    let mut i: usize = 0;
-    let result = integer(source)?;
-    if result.is_none() {
+// math symbols copied from documents, π and superscripts would otherwise read
+// as part of a name
+fn symbol(ch: char) -> Option<Token> {
+    match ch {
+        '×' | '·' => Some(Token::Mult),
+        '÷' => Some(Token::Div),
+        '−' => Some(Token::Sub),
+        '≤' => Some(Token::Le),
+        '≥' => Some(Token::Ge),
+        '≠' => Some(Token::Ne),
+        '√' => Some(Token::Sqrt),
+        'π' => Some(Token::Identifier("pi".to_string())),
+        _ => superscript(ch).map(|digit| Token::Superscript(digit as f64)),
+    }
+}
+
+fn superscript(ch: char) -> Option<u32> {
+    match ch {
+        '⁰' => Some(0),
+        '¹' => Some(1),
+        '²' => Some(2),
+        '³' => Some(3),
+        '⁴'..='⁹' => Some(ch as u32 - '⁴' as u32 + 4),
+        _ => None,
+    }
+}
+
+fn unicode_symbol(source: &str) -> Option<(Token, usize)> {
+    let ch = source.chars().next()?;
+    match symbol(ch)? {
+        Token::Superscript(_) => {
+            let (digits, length) = takes(source, |ch| superscript(ch).is_some()).ok()?;
+            let num = digits.chars().fold(0.0, |num, ch| num * 10.0 + superscript(ch).unwrap() as f64);
+            Some((Token::Superscript(num), length))
+        }
+        token => Some((token, ch.len_utf8())),
+    }
+}
+
+fn keyword(ident: &str) -> Option<Token> {
+    match ident {
+        "xor" => Some(Token::Xor),
+        "and" => Some(Token::And),
+        "or" => Some(Token::Or),
+        "not" => Some(Token::Not),
+        "if" => Some(Token::If),
+        "otherwise" => Some(Token::Otherwise),
+        "let" => Some(Token::Let),
+        "in" => Some(Token::In),
+        _ => None,
+    }
+}
+
+// 12, 1_000.5, .5, 6.022e23, 1e-9, 0x1F, 0b1010, 0o17
+fn number(source: &str) -> Result<Option<(f64, usize)>, &'static str> {
+    let bytes = source.as_bytes();
+    let starts = match bytes.first() {
+        Some(b'0'..=b'9') => true,
+        Some(b'.') => bytes.get(1).is_some_and(u8::is_ascii_digit),
+        _ => false,
+    };
+    if !starts {
         return Ok(None);
     }
-    let int = result.unwrap();
-    i += int.1;
-    let num_str;
-    if i < source.len() {
-        let mut chars = source.get(i..).unwrap().chars();
-        let ch = chars.next().unwrap();
-        if ch == '.' {
-            i += 1;
-            for ch in chars {
-                if ch == '.' {
-                    return Err("Unexpected number");
-                }
-                if ch.is_digit(10) {
-                    i += 1;
-                } else {
-                    break;
-                }
-            }
-            num_str = &source[..i];
-        } else {
-            num_str = int.0;
+    if let Some(radix) = radix(source) {
+        return radix_number(source, radix).map(Some);
+    }
+    let (int, mut i) = digits(source, 10)?;
+    // 0123 is neither decimal nor octal on its own
+    if int.len() > 1 && int.starts_with('0') {
+        return Err("Leading zeros are not allowed in a number");
+    }
+    let mut frac = String::new();
+    if source[i..].starts_with('.') {
+        i += 1;
+        let (digits, length) = digits(&source[i..], 10)?;
+        frac = digits;
+        i += length;
+        if source[i..].starts_with('.') {
+            return Err("Unexpected number");
         }
-    } else {
-        num_str = int.0;
     }
-    return match num_str.replace("_", "").parse::<f64>() {
+    let mut exp = String::from("0");
+    if source[i..].starts_with(['e', 'E']) {
+        let after = &source[i + 1..];
+        let sign = usize::from(after.starts_with(['+', '-']));
+        if after[sign..].starts_with(|ch: char| ch.is_ascii_digit()) {
+            let (digits, length) = digits(&after[sign..], 10)?;
+            exp = format!("{}{}", &after[..sign], digits);
+            i += 1 + sign + length;
+        }
+        // otherwise the e starts a name, as in 2exp(1) or 2e + 1
+    }
+    let int = if int.is_empty() { "0" } else { &int };
+    let frac = if frac.is_empty() { "0" } else { &frac };
+    match format!("{}.{}e{}", int, frac, exp).parse::<f64>() {
+        Ok(num) if num.is_infinite() => Err("Number is too large"),
         Ok(num) => Ok(Some((num, i))),
         Err(_) => Err("Cannot to parse number"),
-    };
+    }
 }
 
-fn integer(source: &str) -> Result<Option<(&str, usize)>, &str> {
-    let mut i: usize = 0;
-    let mut chars = source.chars();
-    let ch = cast_opt_ok!(chars.next());
-    if ch == '0' {
-        return Ok(Some(("0", 1)));
+fn radix(source: &str) -> Option<u32> {
+    match source.get(..2) {
+        Some("0x") | Some("0X") => Some(16),
+        Some("0o") | Some("0O") => Some(8),
+        Some("0b") | Some("0B") => Some(2),
+        _ => None,
     }
-    if ch.is_digit(10) {
-        i += 1;
-        for ch in chars {
-            if ch.is_digit(10) || ch == '_' {
-                i += 1;
-            } else {
-                break;
+}
+
+fn radix_number(source: &str, radix: u32) -> Result<(f64, usize), &'static str> {
+    let (digits, length) = digits(&source[2..], radix)?;
+    let rest = &source[2 + length..];
+    if rest.starts_with(|ch: char| ch.is_alphanumeric()) {
+        return Err("Invalid digit for the number base");
+    }
+    if digits.is_empty() {
+        return Err("Number expected digits after the radix prefix");
+    }
+    if rest.starts_with('.') {
+        return Err("Only decimal numbers can have a fraction");
+    }
+    let num = digits.chars().fold(0.0, |num, ch| num * radix as f64 + ch.to_digit(radix).unwrap() as f64);
+    if num.is_infinite() {
+        return Err("Number is too large");
+    }
+    Ok((num, 2 + length))
+}
+
+// a run of digits in `radix` with single underscores between them,
+// returns the digits without the underscores and the length taken
+fn digits(source: &str, radix: u32) -> Result<(String, usize), &'static str> {
+    let mut digits = String::new();
+    let mut i = 0;
+    for ch in source.chars() {
+        if ch.is_digit(radix) {
+            digits.push(ch);
+        } else if ch == '_' {
+            let next = source[i + 1..].chars().next();
+            if digits.is_empty() || !next.is_some_and(|ch| ch.is_digit(radix)) {
+                return Err("Underscore must be between digits");
             }
+        } else {
+            break;
         }
-        return Ok(Some((&source[..i], i)));
+        i += 1;
     }
-    return Ok(None);
+    Ok((digits, i))
 }
 
 impl<'a> Tokenizer<'a> {
-    fn new(src: &str) -> Tokenizer {
+    fn new(src: &str) -> Tokenizer<'_> {
+        Tokenizer::with_symbols(src, &[])
+    }
+    fn with_symbols(src: &'a str, symbols: &'a [String]) -> Tokenizer<'a> {
         Tokenizer {
             index: 0,
+            chars: 0,
             source: src,
+            depth: 0,
+            continued: false,
+            breaks: Vec::new(),
+            symbols,
+        }
+    }
+    fn next(&mut self) -> Result<Option<(Token, Span)>, &'static str> {
+        let trivia = self.index;
+        if let Some((start, char_start)) = self.skip_trivia()? {
+            let span = Span { start, end: start + 1, char_start, char_end: char_start + 1 };
+            if self.depth > 0 {
+                self.breaks.push(span);
+            } else if trivia > 0 && !self.continued {
+                return Ok(Some((Token::Newline, span)));
+            }
+        }
+        let char_start = self.chars;
+        let (token, start, _) = cast_opt_ok!(self.token()?);
+        match token {
+            Token::LParent | Token::LBrace => self.depth += 1,
+            Token::RParent | Token::RBrace => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        self.continued = token.continues();
+        Ok(Some((token, self.span(start, char_start))))
+    }
+    // from `start` to the current position
+    fn span(&self, start: usize, char_start: usize) -> Span {
+        Span {
+            start,
+            end: self.index,
+            char_start,
+            char_end: self.chars,
         }
     }
-    fn next(&mut self) -> Result<Option<(Token, usize, usize)>, &str> {
+    fn token(&mut self) -> Result<Option<(Token, usize, usize)>, &'static str> {
         if !self.source.is_empty() {
-            self.skip_whitespace();
             if let Some(ch) = self.source.chars().next() {
                 let start = self.index;
+                if let Some(symbol) = self.symbol() {
+                    self.shift(symbol.len());
+                    return Ok(Some((Token::Symbol(symbol.to_string()), start, self.index)));
+                }
                 try_tokenize!(self, start, number, Number);
-                try_tokenize!(self, start, identifier, Identifier);
+                if let Some((token, length)) = unicode_symbol(self.source) {
+                    self.shift(length);
+                    return Ok(Some((token, start, self.index)));
+                }
+                if let Some((ident, length)) = identifier(self.source)? {
+                    self.shift(length);
+                    let token = keyword(&ident).unwrap_or(Token::Identifier(ident));
+                    return Ok(Some((token, start, self.index)));
+                }
+                try_str!(self, start, "!=", Ne);
+                try_str!(self, start, "!!", DoubleFact);
+                try_str!(self, start, "//", IntDiv);
+                try_str!(self, start, "<<", Shl);
+                try_str!(self, start, ">>", Shr);
+                try_str!(self, start, "==", Eq);
+                try_str!(self, start, "<=", Le);
+                try_str!(self, start, ">=", Ge);
+                try_one_char!(self, start, ch, '<', Lt);
+                try_one_char!(self, start, ch, '>', Gt);
+                try_one_char!(self, start, ch, '=', Assign);
+                try_one_char!(self, start, ch, '?', Question);
+                try_one_char!(self, start, ch, ':', Colon);
+                try_one_char!(self, start, ch, '!', Fact);
                 try_one_char!(self, start, ch, '^', Pow);
                 try_one_char!(self, start, ch, '+', Add);
                 try_one_char!(self, start, ch, '-', Sub);
                 try_one_char!(self, start, ch, '*', Mult);
                 try_one_char!(self, start, ch, '/', Div);
+                try_one_char!(self, start, ch, '%', Mod);
+                try_one_char!(self, start, ch, '&', BitAnd);
+                try_one_char!(self, start, ch, '|', BitOr);
                 try_one_char!(self, start, ch, '(', LParent);
                 try_one_char!(self, start, ch, ')', RParent);
+                try_one_char!(self, start, ch, '{', LBrace);
+                try_one_char!(self, start, ch, '}', RBrace);
                 try_one_char!(self, start, ch, ',', Comma);
+                try_one_char!(self, start, ch, ';', Semicolon);
+                self.shift(ch.len_utf8());
+                return Ok(Some((Token::Unknown(ch), start, self.index)));
+            }
+            Ok(None)
+        } else {
+            Ok(None)
+        }
+    }
+    // a word symbol such as `choose` has to end where the name ends
+    fn symbol(&self) -> Option<&'a str> {
+        let name = |ch: char| ch.is_alphanumeric() || ch == '_';
+        self.symbols.iter().map(|symbol| symbol.as_str()).find(|symbol| {
+            self.source.starts_with(symbol) && !(symbol.ends_with(name) && self.source[symbol.len()..].starts_with(name))
+        })
+    }
+    // skips whitespace, `# line` and `/* block */` comments, gives the byte and
+    // char offset of the first line break that is not inside a comment
+    fn skip_trivia(&mut self) -> Result<Option<(usize, usize)>, &'static str> {
+        let mut newline = None;
+        loop {
+            if let Ok((text, length)) = takes(self.source, |a| a.is_whitespace()) {
+                if let (None, Some(i)) = (newline, text.find('\n')) {
+                    newline = Some((self.index + i, self.chars + text[..i].chars().count()));
+                }
+                self.shift(length);
+            } else if self.source.starts_with('#') {
+                self.shift(self.source.find('\n').unwrap_or(self.source.len()));
+            } else if self.source.starts_with("/*") {
+                match self.source[2..].find("*/") {
+                    Some(i) => self.shift(i + 4),
+                    None => return Err("Unterminated block comment"),
+                }
+            } else {
+                return Ok(newline);
+            }
+        }
+    }
+    // the tokenizer stops at the start of a bad literal, skip all of it
+    fn literal_error(&mut self, message: &'static str) -> MooError {
+        let (start, char_start) = (self.index, self.chars);
+        // an unterminated comment runs to the end
+        let mut length = if self.source.starts_with("/*") { self.source.len() } else { 0 };
+        let mut prev = ' ';
+        for ch in self.source.chars().take_while(|_| !self.source.starts_with("/*")) {
+            let sign = (ch == '+' || ch == '-') && (prev == 'e' || prev == 'E');
+            if length > 0 && !(ch.is_alphanumeric() || ch == '_' || ch == '.' || sign) {
+                break;
+            }
+            length += ch.len_utf8();
+            prev = ch;
+        }
+        self.shift(length);
+        MooError {
+            message,
+            span: Some(self.span(start, char_start)),
+        }
+    }
+    fn shift(&mut self, length: usize) {
+        self.chars += self.source[..length].chars().count();
+        self.source = &self.source[length..];
+        self.index += length;
+    }
+}
+
+/// Rewrites the math symbols of `source` to ASCII, `2×π·r²` gives `2*pi*r^2`
+/// and `√x` gives `sqrt(x)`. Names, numbers and spacing are kept as written,
+/// other non-ASCII whitespace becomes a space.
+pub fn normalize(source: &str) -> String {
+    let mut tokenizer = Tokenizer::new(source);
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
+    while let Ok(Some(token)) = tokenizer.next() {
+        tokens.push(token);
+    }
+    let mut ascii = String::new();
+    let mut copied = 0;
+    // tokens after which a `sqrt(` opened for `√x` is closed
+    let mut closes: Vec<usize> = Vec::new();
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        ascii.extend(source[copied..span.start].chars().map(|ch| if ch.is_whitespace() && !ch.is_ascii() { ' ' } else { ch }));
+        let text = &source[span.start..span.end];
+        let symbol = match token {
+            _ if text.is_ascii() => text.to_string(),
+            Token::Superscript(num) => format!("^{}", num),
+            Token::Sqrt if matches!(tokens.get(i + 1), Some((Token::LParent, _))) => "sqrt".to_string(),
+            Token::Sqrt => {
+                closes.push(sqrt_operand_end(&tokens, i + 1));
+                "sqrt(".to_string()
+            }
+            token => token.to_string(),
+        };
+        // keep a rewritten name apart from the names around it, `xπ` is `x pi`
+        let name = |ch: char| ch.is_alphanumeric() || ch == '_';
+        if !text.is_ascii() && symbol.starts_with(name) && ascii.ends_with(name) {
+            ascii.push(' ');
+        }
+        ascii.push_str(&symbol);
+        if !text.is_ascii() && symbol.ends_with(name) && source[span.end..].starts_with(name) {
+            ascii.push(' ');
+        }
+        ascii.extend(closes.iter().filter(|&&close| close == i).map(|_| ')'));
+        copied = span.end;
+    }
+    ascii.push_str(&source[copied..]);
+    ascii
+}
+
+// last token of the operand `√` takes, the same one the parser reads
+fn sqrt_operand_end(tokens: &[(Token, Span)], mut i: usize) -> usize {
+    while let Some((Token::Sqrt, _)) = tokens.get(i) {
+        i += 1;
+    }
+    if let Some((Token::Identifier(_), _)) = tokens.get(i) {
+        if let Some((Token::LParent, _)) = tokens.get(i + 1) {
+            i += 1;
+        }
+    }
+    if let Some((Token::LParent, _)) | Some((Token::LBrace, _)) = tokens.get(i) {
+        let mut depth = 0;
+        while let Some((token, _)) = tokens.get(i) {
+            match token {
+                Token::LParent | Token::LBrace => depth += 1,
+                Token::RParent | Token::RBrace => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            i += 1;
+        }
+    }
+    while let Some((Token::Fact, _)) | Some((Token::DoubleFact, _)) | Some((Token::Superscript(_), _)) = tokens.get(i + 1) {
+        i += 1;
+    }
+    i.min(tokens.len() - 1)
+}
+
+// `|` opens an absolute value where an operand is expected and closes the innermost
+// one opened at the same bracket depth after an operand, any other `|` is bitwise or
+// in integer mode. `|a|b|c|` reads as `|a| b |c|` or `|a |b| c|`, so a closing bar
+// followed by an operand is an error at that bar, a `|` counts as one when it opens a bar.
+fn abs_bars(tokens: &mut [(Token, Span)], integer_mode: bool) -> Result<(), MooError> {
+    use crate::Token::*;
+    let mut depth: usize = 0;
+    // bracket depth of every open bar, innermost last
+    let mut open: Vec<usize> = Vec::new();
+    for i in 0..tokens.len() {
+        match tokens[i].0 {
+            LParent | LBrace => {
+                depth += 1;
+                continue;
+            }
+            RParent | RBrace => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            BitOr => {}
+            _ => continue,
+        }
+        let after_operand = i > 0 && matches!(
+            tokens[i - 1].0,
+            Number(_) | Identifier(_) | RParent | RBrace | AbsClose | Fact | DoubleFact | Superscript(_)
+        );
+        if after_operand && open.last() == Some(&depth) {
+            let starts = |next: Option<&(Token, Span)>| {
+                next.is_some_and(|(token, _)| matches!(token, Number(_) | Identifier(_) | LParent | LBrace | Sqrt | If))
+            };
+            // `||x||` closes twice, the second bar has no operand after it
+            let operand = match tokens.get(i + 1) {
+                Some((BitOr, _)) => !integer_mode && starts(tokens.get(i + 2)),
+                next => starts(next),
+            };
+            if operand {
+                return Err(MooError { message: "Ambiguous '|', write abs() or add parentheses", span: Some(tokens[i].1) });
+            }
+            open.pop();
+            tokens[i].0 = AbsClose;
+        } else if !after_operand || !integer_mode {
+            open.push(depth);
+            tokens[i].0 = AbsOpen;
+        }
+    }
+    Ok(())
+}
+
+/// Result of [`Moo::parse_recovering`], a program with [`Expression::Error`] in
+/// place of the parts that did not parse and every error found, in source order.
+#[derive(Debug)]
+pub struct Recovery {
+    pub program: Option<Program>,
+    pub diagnostics: Vec<MooError>,
+}
+
+// the tokens being parsed, in recovering mode also the errors recovered from
+struct Cursor<'t> {
+    iter: Iter<'t, (Token, Span)>,
+    tokens: &'t [(Token, Span)],
+    diagnostics: Option<Vec<MooError>>,
+    // nesting depth of the expression being read and nodes read so far
+    depth: usize,
+    nodes: usize,
+    // spans of the nodes built so far, children before their parent
+    spans: Vec<Span>,
+    // where an error at the end of the tokens is reported
+    end: Option<Span>,
+}
+
+// a position to rewind to, with the nesting depth and the nodes built there
+#[derive(Clone, Copy)]
+struct Checkpoint {
+    index: Option<usize>,
+    depth: usize,
+    spans: usize,
+}
+
+impl<'t> Cursor<'t> {
+    fn new(tokens: &'t Vec<(Token, Span)>, recovering: bool) -> Cursor<'t> {
+        Cursor {
+            iter: Iter::new(tokens),
+            tokens,
+            diagnostics: if recovering { Some(Vec::new()) } else { None },
+            depth: 0,
+            nodes: 0,
+            spans: Vec::new(),
+            end: None,
+        }
+    }
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint { index: self.iter.checkpoint(), depth: self.depth, spans: self.spans.len() }
+    }
+    fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.iter.rewind(checkpoint.index);
+        self.depth = checkpoint.depth;
+        self.spans.truncate(checkpoint.spans);
+    }
+    // index of the token `next` reads
+    fn next_index(&self) -> usize {
+        self.iter.checkpoint().map_or(0, |index| index + 1)
+    }
+    // records the span of a node read from token `first` up to the last token read,
+    // a node without tokens takes the span of the token it was found at
+    fn node(&mut self, first: usize, expr: Expression) -> Expression {
+        let last = self.iter.checkpoint().filter(|&last| last >= first).unwrap_or(first);
+        let end = self.tokens.len().saturating_sub(1);
+        let span = match (self.tokens.get(first.min(end)), self.tokens.get(last.min(end))) {
+            (Some(a), Some(b)) => Span { start: a.1.start, end: b.1.end, char_start: a.1.char_start, char_end: b.1.char_end },
+            _ => Span::default(),
+        };
+        self.spans.push(span);
+        expr
+    }
+    fn at_end(&mut self) -> bool {
+        let start = self.checkpoint();
+        let end = self.next().is_none();
+        self.rewind(start);
+        end
+    }
+    // the token the error was found at, the last one read or else the next one,
+    // past the last token the end of the source
+    fn error_span(&mut self) -> Option<Span> {
+        if let Some(token) = self.current() {
+            return Some(token.1);
+        }
+        let start = self.checkpoint();
+        let span = self.next().map(|token| token.1);
+        self.rewind(start);
+        span.or(self.end)
+    }
+}
+
+impl<'t> std::ops::Deref for Cursor<'t> {
+    type Target = Iter<'t, (Token, Span)>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.iter
+    }
+}
+
+impl std::ops::DerefMut for Cursor<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.iter
+    }
+}
+
+fn statement_end(token: &Token) -> bool {
+    matches!(token, Token::Semicolon | Token::Newline)
+}
+
+// a line break inside brackets that are never closed ends the statement, so
+// recovery from a missing `)` goes on at the next line, `breaks` are in order
+fn break_unclosed(tokens: Vec<(Token, Span)>, breaks: &[Span]) -> Vec<(Token, Span)> {
+    use crate::Token::*;
+    let mut open = Vec::new();
+    let mut closed = vec![false; tokens.len()];
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            LParent | LBrace => open.push(i),
+            RParent | RBrace => {
+                if let Some(j) = open.pop() {
+                    closed[j] = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    if open.is_empty() {
+        return tokens;
+    }
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut breaks = breaks.iter().peekable();
+    // for every bracket open at this point, whether it is closed later
+    let mut stack: Vec<bool> = Vec::new();
+    for (i, token) in tokens.into_iter().enumerate() {
+        while let Some(span) = breaks.next_if(|span| span.start < token.1.start) {
+            if !stack.is_empty() && !stack.contains(&true) {
+                result.push((Newline, *span));
+            }
+        }
+        match token.0 {
+            LParent | LBrace => stack.push(closed[i]),
+            RParent | RBrace => {
+                stack.pop();
+            }
+            _ => {}
+        }
+        result.push(token);
+    }
+    result
+}
+
+pub struct Moo<'a> {
+    functions: Functions<'a>,
+    operators: Operators,
+    config: Config,
+}
+
+impl<'a> Moo<'a> {
+    /// A parser with the built-in functions and those `add_on` inserts, a name
+    /// already taken is replaced. Functions of two arguments or that report
+    /// errors are added with [`Moo::register`].
+    pub fn new(add_on: fn(functions: &mut HashMap<&str, Function>)) -> Moo<'a> {
+        let mut functions = Functions::default();
+        functions.insert("sin", |v| {
+            f64::sin(v)
+        });
+        functions.insert("cos", |v| {
+            f64::cos(v)
+        });
+        functions.insert("abs", |v| {
+            f64::abs(v)
+        });
+        functions.insert("sqrt", |v| {
+            f64::sqrt(v)
+        });
+        functions.insert_checked("fact", special::factorial);
+        functions.insert_checked("dfact", special::double_factorial);
+        let mut added: HashMap<&'a str, Function> = HashMap::new();
+        add_on(&mut added);
+        for (name, function) in added {
+            functions.insert(name, function);
+        }
+        Moo {
+            functions,
+            operators: Operators::default(),
+            config: Config::default(),
+        }
+    }
+    /// Changes the parser options, e.g. `moo.configure(|config| config.integer_mode = true)`.
+    pub fn configure(&mut self, change: fn(config: &mut Config)) {
+        change(&mut self.config);
+    }
+    /// Adds a function module, e.g. [`special::register`].
+    pub fn register(&mut self, module: fn(functions: &mut Functions<'a>)) {
+        module(&mut self.functions);
+    }
+    /// Adds custom operators, e.g.
+    /// `moo.add_operators(|operators| operators.insert_infix("**", operators::POWER, Associativity::Right, f64::powf))`.
+    pub fn add_operators(&mut self, add: fn(operators: &mut Operators)) {
+        add(&mut self.operators);
+    }
+    pub fn parse(&self, source: &str) -> Result<Option<Program>, &'static str> {
+        self.parse_in(source, &Environment::default())
+    }
+    /// Parses `source` with the variables of `env` in scope, the program should
+    /// then be evaluated with [`Program::eval`].
+    pub fn parse_in(&self, source: &str, env: &Environment) -> Result<Option<Program>, &'static str> {
+        self.check_length(source)?;
+        let mut tokenizer = Tokenizer::with_symbols(source, self.operators.symbols());
+        let mut tokens: Vec<(Token, Span)> = Vec::new();
+        while let Some(token) = tokenizer.next()? {
+            if let Token::Unknown(_) = token.0 {
+                return Err("Unexpected character");
+            }
+            self.check_tokens(&tokens)?;
+            tokens.push(token);
+        }
+        abs_bars(&mut tokens, self.config.integer_mode).map_err(|error| error.message)?;
+        self.ast_program(&mut Cursor::new(&tokens, false), env)
+    }
+    /// Parses `source` past its errors, syncing at `)`, `,` and the end of the
+    /// statement, so an editor can show every syntax error at once. A bracket that
+    /// is never closed ends at the next line break, an error at the end of the
+    /// input has an empty span at the end of `source`.
+    pub fn parse_recovering(&self, source: &str) -> Recovery {
+        let mut tokenizer = Tokenizer::with_symbols(source, self.operators.symbols());
+        let mut tokens: Vec<(Token, Span)> = Vec::new();
+        let mut diagnostics = Vec::new();
+        // past a limit there is nothing to recover
+        let limit = |message| Recovery { program: None, diagnostics: vec![MooError { message, span: None }] };
+        if let Err(message) = self.check_length(source) {
+            return limit(message);
+        }
+        loop {
+            if let Err(message) = self.check_tokens(&tokens) {
+                return limit(message);
+            }
+            match tokenizer.next() {
+                Ok(Some((Token::Unknown(ch), span))) => {
+                    diagnostics.push(MooError { message: "Unexpected character", span: Some(span) });
+                    tokens.push((Token::Unknown(ch), span));
+                }
+                Ok(Some(token)) => tokens.push(token),
+                Ok(None) => break,
+                // the literal is read as an error node
+                Err(message) => {
+                    let error = tokenizer.literal_error(message);
+                    tokens.push((Token::Unknown('\u{fffd}'), error.span.unwrap()));
+                    diagnostics.push(error);
+                }
+            }
+        }
+        if let Err(error) = abs_bars(&mut tokens, self.config.integer_mode) {
+            diagnostics.push(error);
+        }
+        let tokens = break_unclosed(tokens, &tokenizer.breaks);
+        let mut cursor = Cursor::new(&tokens, true);
+        cursor.diagnostics = Some(diagnostics);
+        let end = source.chars().count();
+        cursor.end = Some(Span { start: source.len(), end: source.len(), char_start: end, char_end: end });
+        let program = self.ast_program(&mut cursor, &Environment::default()).unwrap_or(None);
+        let mut diagnostics = cursor.diagnostics.unwrap();
+        diagnostics.sort_by_key(|error| error.span.map(|span| span.start));
+        Recovery { program, diagnostics }
+    }
+    fn check_length(&self, source: &str) -> Result<(), &'static str> {
+        if source.len() > self.config.max_source_length {
+            return Err("Source is too long");
+        }
+        Ok(())
+    }
+    // before another token is added
+    fn check_tokens<T>(&self, tokens: &[T]) -> Result<(), &'static str> {
+        if tokens.len() >= self.config.max_tokens {
+            return Err("Too many tokens");
+        }
+        Ok(())
+    }
+    // one level deeper, the caller puts the depth back once its expression is read
+    // and a rewind puts back the depth of the checkpoint
+    fn ast_deeper(&self, iter: &mut Cursor) -> Result<(), &'static str> {
+        if iter.depth >= self.config.max_nesting_depth {
+            return Err("Expression is nested too deeply");
+        }
+        iter.depth += 1;
+        Ok(())
+    }
+    fn ast_nodes(&self, iter: &mut Cursor, count: usize) -> Result<(), &'static str> {
+        iter.nodes += count;
+        if iter.nodes > self.config.max_nodes {
+            return Err("Expression has too many nodes");
+        }
+        Ok(())
+    }
+    // in recovering mode the error is recorded, the tokens from `start` up to the
+    // next `sync` token or statement end at the same depth, or the next newline,
+    // are skipped and an error node takes their place, otherwise the error is returned
+    fn recover(&self, iter: &mut Cursor, start: Checkpoint, error: &'static str, sync: fn(&Token) -> bool) -> Result<Expression, &'static str> {
+        use crate::Token::*;
+        if iter.diagnostics.is_none() {
+            return Err(error);
+        }
+        // one error per token, the first is the cause of the others
+        let span = iter.error_span();
+        let first = start.index.map_or(0, |index| index + 1);
+        let diagnostics = iter.diagnostics.as_mut().unwrap();
+        if span.is_none() || diagnostics.iter().all(|diagnostic| diagnostic.span != span) {
+            diagnostics.push(MooError { message: error, span });
+        }
+        iter.rewind(start);
+        let mut depth = 0;
+        loop {
+            let before = iter.checkpoint();
+            let token = match iter.next() {
+                Some((token, _)) => token,
+                None => break,
+            };
+            // a newline inside brackets is one of an unclosed bracket or `|`
+            if matches!(token, Newline) || (depth == 0 && (sync(token) || statement_end(token))) {
+                iter.rewind(before);
+                break;
+            }
+            match token {
+                LParent | LBrace | AbsOpen => depth += 1,
+                RParent | RBrace | AbsClose if depth > 0 => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(iter.node(first, Expression::Error))
+    }
+    fn ast_program(&self, iter: &mut Cursor, env: &Environment) -> Result<Option<Program>, &'static str> {
+        let mut definitions: Vec<Definition> = Vec::new();
+        let mut assignments: Vec<Assignment> = Vec::new();
+        let mut variables: Vec<String> = env.variables.keys().cloned().collect();
+        let mut body: Option<Expression> = None;
+        let mut spans = NodeSpans::default();
+        // span of the last statement that did not parse
+        let mut failed = None;
+        while !iter.at_end() {
+            let start = iter.checkpoint();
+            if body.is_some() {
+                // the expression read before was not the last statement
+                body = None;
+                spans.body.clear();
+                self.recover(iter, start, "Program expected an expression as the last statement", |_| true)?;
+            }
+            let (mark, defined) = (iter.spans.len(), definitions.len());
+            match self.ast_statement(iter, &mut definitions, &mut assignments, &mut variables) {
+                Ok(expr) => {
+                    let statement = iter.spans.split_off(mark);
+                    match expr {
+                        Some(_) => spans.body = statement,
+                        None if definitions.len() > defined => spans.definitions.push(statement),
+                        None => spans.assignments.push(statement),
+                    }
+                    body = expr;
+                }
+                Err(error) => {
+                    self.recover(iter, start, error, statement_end)?;
+                    failed = iter.spans.last().copied();
+                }
+            }
+            let end = iter.checkpoint();
+            if let Err(error) = self.ast_statement_end(iter) {
+                self.recover(iter, end, error, statement_end)?;
+                self.ast_statement_end(iter)?;
+            }
+        }
+        let body = match body {
+            Some(body) => body,
+            None => match assignments.last() {
+                Some(last) => {
+                    // the assigned name stands where the value is
+                    spans.body = spans.assignments.last().and_then(|value| value.last().copied()).into_iter().collect();
+                    Expression::Primitive(Primitive::Identifier(last.name.clone()))
+                }
+                None if failed.is_some() => {
+                    spans.body = failed.into_iter().collect();
+                    Expression::Error
+                }
+                None if definitions.is_empty() => return Ok(None),
+                None => {
+                    let start = Checkpoint { index: None, depth: 0, spans: iter.spans.len() };
+                    let error = self.recover(iter, start, "Program expected an expression", statement_end)?;
+                    spans.body = iter.spans.split_off(start.spans);
+                    error
+                }
+            },
+        };
+        Ok(Some(Program { definitions, assignments, body, config: self.config, spans }))
+    }
+    // one definition, assignment or expression, the expression is returned
+    fn ast_statement(
+        &self,
+        iter: &mut Cursor,
+        definitions: &mut Vec<Definition>,
+        assignments: &mut Vec<Assignment>,
+        variables: &mut Vec<String>,
+    ) -> Result<Option<Expression>, &'static str> {
+        if let Some((name, params)) = self.ast_definition_header(iter)? {
+            if self.functions.contains(&name) {
+                return Err("Cannot redefine a built-in function");
+            }
+            if definitions.iter().any(|d| d.name == name) {
+                return Err("Function is already defined");
+            }
+            let start = iter.checkpoint();
+            let body = self.ast_expression(iter).and_then(|body| {
+                let body = body.ok_or("Function definition expected a body")?;
+                let mut scope = params.iter().map(|p| p.as_str()).collect();
+                body.resolve(&mut scope, definitions, Some((&name, params.len())))?;
+                Ok(body)
+            });
+            let body = body.or_else(|error| self.recover(iter, start, error, statement_end))?;
+            definitions.push(Definition { name, params, body });
+            return Ok(None);
+        }
+        if let Some(name) = self.ast_assignment_header(iter)? {
+            if self.functions.contains(&name) {
+                return Err("Cannot assign to a built-in function");
+            }
+            let start = iter.checkpoint();
+            let value = self.ast_expression(iter).and_then(|value| {
+                let value = value.ok_or("Assignment expected a value")?;
+                value.resolve(&mut variables.iter().map(|v| v.as_str()).collect(), definitions, None)?;
+                Ok(value)
+            });
+            let value = value.or_else(|error| self.recover(iter, start, error, statement_end))?;
+            variables.push(name.clone());
+            assignments.push(Assignment { name, value });
+            return Ok(None);
+        }
+        let body = self.ast_expression(iter)?;
+        if let Some(body) = &body {
+            body.resolve(&mut variables.iter().map(|v| v.as_str()).collect(), definitions, None)?;
+        }
+        Ok(body)
+    }
+    // `;` or a newline, repeated separators are skipped
+    fn ast_statement_end(&self, iter: &mut Cursor) -> Result<(), &'static str> {
+        match iter.next() {
+            Some((Token::Semicolon, _)) | Some((Token::Newline, _)) => {}
+            None => return Ok(()),
+            Some(_) => return Err("Expected ';' or a newline between statements"),
+        }
+        loop {
+            match iter.next() {
+                Some((Token::Semicolon, _)) | Some((Token::Newline, _)) => {}
+                Some(_) => {
+                    iter.prev();
+                    return Ok(());
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+    // name =
+    fn ast_assignment_header(&self, iter: &mut Cursor) -> Result<Option<String>, &'static str> {
+        let start = iter.checkpoint();
+        match (iter.next(), iter.next()) {
+            (Some((Token::Identifier(name), _)), Some((Token::Assign, _))) => Ok(Some(name.clone())),
+            _ => {
+                iter.rewind(start);
+                Ok(None)
+            }
+        }
+    }
+    // name(params) =
+    fn ast_definition_header(&self, iter: &mut Cursor) -> Result<Option<(String, Vec<String>)>, &'static str> {
+        use crate::Token::*;
+        let start = iter.checkpoint();
+        let name = match (iter.next(), iter.next()) {
+            (Some((Identifier(name), _)), Some((LParent, _))) => name.clone(),
+            _ => {
+                iter.rewind(start);
+                return Ok(None);
+            }
+        };
+        let mut params: Vec<String> = Vec::new();
+        loop {
+            match iter.next() {
+                Some((Identifier(param), _)) => params.push(param.clone()),
+                Some((RParent, _)) if params.is_empty() => break,
+                _ => {
+                    iter.rewind(start);
+                    return Ok(None);
+                }
+            }
+            match iter.next() {
+                Some((Comma, _)) => {}
+                Some((RParent, _)) => break,
+                _ => {
+                    iter.rewind(start);
+                    return Ok(None);
+                }
+            }
+        }
+        match iter.next() {
+            Some((Assign, _)) => {}
+            _ => {
+                iter.rewind(start);
+                return Ok(None);
+            }
+        }
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].contains(param) {
+                return Err("Duplicate parameter");
             }
-            Ok(None)
-        } else {
-            Ok(None)
         }
+        Ok(Some((name, params)))
     }
-    fn skip_whitespace(&mut self) {
-        match takes(self.source, |a| a.is_whitespace()) {
-            Ok(e) => {
-                self.shift(e.1);
+    fn ast_expression(&self, iter: &mut Cursor) -> Result<Option<Expression>, &'static str> {
+        let depth = iter.depth;
+        self.ast_deeper(iter)?;
+        let first = iter.next_index();
+        let expr = match iter.next() {
+            Some((Token::Let, _)) => {
+                let expr = self.ast_let(iter)?;
+                Some(iter.node(first, expr))
             }
-            Err(_) => {}
+            Some(_) => {
+                iter.prev();
+                self.ast_conditional_expression(iter)?
+            }
+            None => None,
         };
+        iter.depth = depth;
+        Ok(expr)
     }
-    fn shift(&mut self, length: usize) {
-        self.source = &self.source[length..];
-        self.index += length;
+    // let name = value in body
+    fn ast_let(&self, iter: &mut Cursor) -> Result<Expression, &'static str> {
+        let name = match iter.next() {
+            Some((Token::Identifier(name), _)) if !self.functions.contains(name) => name.clone(),
+            _ => return Err("let expected a variable name"),
+        };
+        match iter.next() {
+            Some((Token::Assign, _)) => {}
+            _ => return Err("let expected '='"),
+        }
+        let value = self.ast_expression(iter)?.ok_or("let expected a value")?;
+        match iter.next() {
+            Some((Token::In, _)) => {}
+            _ => return Err("let expected 'in'"),
+        }
+        let body = self.ast_expression(iter)?.ok_or("let expected an expression after 'in'")?;
+        self.ast_nodes(iter, 1)?;
+        Ok(Expression::Let((name, Box::new(value), Box::new(body))))
     }
-}
-
-pub struct Moo<'a> {
-    functions: HashMap<&'a str, Function>,
-}
-
-impl<'a> Moo<'a> {
-    pub fn new(add_on: fn(functions: &mut HashMap<&str, Function>)) -> Moo<'a> {
-        let mut functions: HashMap<&str, Function> = HashMap::new();
-        functions.insert("sin", |v| {
-            f64::sin(v)
-        });
-        functions.insert("cos", |v| {
-            f64::cos(v)
-        });
-        functions.insert("abs", |v| {
-            f64::abs(v)
-        });
-        add_on(&mut functions);
-        Moo {
-            functions,
+    fn ast_conditional_expression(&self, iter: &mut Cursor) -> Result<Option<Expression>, &'static str> {
+        let first = iter.next_index();
+        let condition = cast_opt_ok!(self.ast_operation(iter, 0)?);
+        match iter.next() {
+            Some((Token::Question, _)) => {}
+            Some(_) => {
+                iter.prev();
+                return Ok(Some(condition));
+            }
+            None => return Ok(Some(condition)),
         }
+        self.ast_nodes(iter, 1)?;
+        let then = self.ast_expression(iter)?.ok_or("Conditional expected an expression after '?'")?;
+        match iter.next() {
+            Some((Token::Colon, _)) => {}
+            _ => return Err("Conditional expected ':'"),
+        }
+        self.ast_deeper(iter)?;
+        let otherwise = self.ast_conditional_expression(iter)?.ok_or("Conditional expected an expression after ':'")?;
+        Ok(Some(iter.node(first, Expression::Conditional((Box::new(condition), Box::new(then), Box::new(otherwise))))))
     }
-    pub fn parse(&self, source: &str) -> Result<Option<Program>, &str> {
-        let mut tokenizer = Tokenizer::new(source);
-        let mut tokens: Vec<(Token, usize, usize)> = Vec::new();
+    // binding power parser for the operators between the conditional and the
+    // primitives, operators are read while their precedence is above `min`
+    fn ast_operation(&self, iter: &mut Cursor, min: u8) -> Result<Option<Expression>, &'static str> {
+        use crate::Expression::*;
+        let depth = iter.depth;
+        let first = iter.next_index();
+        let mut left = cast_opt_ok!(self.ast_prefix(iter)?);
+        // precedence of the last non-associative operator read at this level
+        let mut chain = None;
         loop {
-            let result = tokenizer.next();
-            if result.is_ok() {
-                match result.unwrap() {
-                    Some(token) => {
-                        tokens.push(token);
+            // every operator of a chain nests, `1 + 2 + 3` is `(1 + 2) + 3`
+            let start = iter.checkpoint();
+            let token = iter.next_index();
+            let operation = match iter.next().and_then(|tnk| self.ast_infix(&tnk.0)) {
+                Some(operation) => operation,
+                None => {
+                    iter.rewind(start);
+                    match self.ast_implicit(iter)? {
+                        Some(operation) => operation,
+                        None => break,
                     }
-                    None => break,
                 }
-            } else {
+            };
+            let precedence = operation.precedence();
+            if precedence <= min {
+                iter.rewind(start);
                 break;
             }
-        }
-        let token_iter = &mut Iter::new(&tokens);
-        Ok(self.ast_program(token_iter)?)
-    }
-    fn ast_program(&self, iter: &mut Iter<(Token, usize, usize)>) -> Result<Option<Program>, &str> {
-        let body = self.ast_additive_expression(iter)?;
-        if body.is_some() {
-            return Ok(Some(Program { body: body.unwrap() }));
-        }
-        return Ok(None);
-    }
-    fn ast_additive_expression(&self, iter: &mut Iter<(Token, usize, usize)>) -> Result<Option<Expression>, &str> {
-        let left = self.ast_multiplicative_expression(iter)?;
-        if left.is_none() {
-            return Ok(None);
-        }
-        if let Some(tnk) = iter.next() {
-            use crate::Token::*;
-            use crate::Expression::*;
-            match tnk.0 {
-                Add => {
-                    let right = self.ast_additive_expression(iter)?;
-                    if right.is_none() {
-                        return Err("ERROR 8");
-                    }
-                    return Ok(Some(ExpressionOperation((Box::new(left.unwrap()), '+', Box::new(right.unwrap())))));
+            if chain == Some(precedence) {
+                if precedence == operators::COMPARISON {
+                    return Err("Comparison operators cannot be chained");
                 }
-                Sub => {
-                    let right = self.ast_additive_expression(iter)?;
-                    if right.is_none() {
-                        return Err("ERROR 7");
+                return Err("Non-associative operators cannot be chained");
+            }
+            self.ast_deeper(iter)?;
+            self.ast_nodes(iter, if let Operation::Superscript(_) = operation { 2 } else { 1 })?;
+            let associativity = operation.associativity();
+            // right associative operators read an operator of the same precedence
+            // into their right operand
+            let bound = if associativity == Associativity::Right { precedence.saturating_sub(1) } else { precedence };
+            let expr = match operation {
+                Operation::Binary(op) => {
+                    if op.is_bitwise() && !self.config.integer_mode {
+                        return Err("Bitwise operators need integer mode");
                     }
-                    return Ok(Some(ExpressionOperation((Box::new(left.unwrap()), '-', Box::new(right.unwrap())))));
+                    let right = self.ast_operation(iter, bound)?.ok_or("Operator expected an operand")?;
+                    ExpressionOperation((Box::new(left), op, Box::new(right)))
                 }
-                _ => {
-                    iter.prev();
+                Operation::Implicit(_) => {
+                    let right = self.ast_operation(iter, bound)?.ok_or("Operator expected an operand")?;
+                    ExpressionOperation((Box::new(left), Operator::Mult, Box::new(right)))
+                }
+                Operation::Fact(name) => self.builtin(name, vec![left])?,
+                Operation::Superscript(num) => {
+                    let power = iter.node(token, Primitive(crate::Primitive::Number(num)));
+                    ExpressionOperation((Box::new(left), Operator::Pow, Box::new(power)))
                 }
+                Operation::Custom(op) if op.fixity == Fixity::Postfix => CustomOperation((op.clone(), vec![left])),
+                Operation::Custom(op) => {
+                    let right = self.ast_operation(iter, bound)?.ok_or("Operator expected an operand")?;
+                    CustomOperation((op.clone(), vec![left, right]))
+                }
+                Operation::Unary(_) => unreachable!(),
             };
+            left = iter.node(first, expr);
+            if associativity == Associativity::None {
+                chain = Some(precedence);
+            }
         }
-        Ok(left)
+        iter.depth = depth;
+        Ok(Some(left))
     }
-    fn ast_multiplicative_expression(&self, iter: &mut Iter<(Token, usize, usize)>) -> Result<Option<Expression>, &str> {
-        let left = self.ast_exponential_expression(iter)?;
-        if left.is_none() {
-            return Ok(None);
-        }
-        if let Some(tnk) = iter.next() {
-            use crate::Token::*;
-            use crate::Expression::*;
-            match tnk.0 {
-                Mult => {
-                    let right = self.ast_exponential_expression(iter)?;
-                    if right.is_none() {
-                        return Err("ERROR 6");
-                    }
-                    return Ok(Some(ExpressionOperation((Box::new(left.unwrap()), '*', Box::new(right.unwrap())))));
-                }
-                Div => {
-                    let right = self.ast_exponential_expression(iter)?;
-                    if right.is_none() {
-                        return Err("ERROR 5");
-                    }
-                    return Ok(Some(ExpressionOperation((Box::new(left.unwrap()), '/', Box::new(right.unwrap())))));
+    // `-`, `not` and custom prefix operators
+    fn ast_prefix(&self, iter: &mut Cursor) -> Result<Option<Expression>, &'static str> {
+        let first = iter.next_index();
+        let operation = match iter.next() {
+            Some((Token::Sub, _)) => Operation::Unary(UnaryOperator::Neg),
+            Some((Token::Not, _)) => Operation::Unary(UnaryOperator::Not),
+            Some((Token::Symbol(symbol), _)) if self.operators.prefix(symbol).is_some() => {
+                Operation::Custom(self.operators.prefix(symbol).unwrap())
+            }
+            Some(_) => {
+                iter.prev();
+                let primitive = self.ast_primitive(iter)?;
+                if primitive.is_some() {
+                    self.ast_nodes(iter, 1)?;
                 }
-                _ => {
-                    iter.prev();
+                return Ok(primitive);
+            }
+            None => return Ok(None),
+        };
+        let depth = iter.depth;
+        self.ast_deeper(iter)?;
+        self.ast_nodes(iter, 1)?;
+        let operand = self.ast_operation(iter, operation.precedence())?.ok_or("Operator expected an operand")?;
+        iter.depth = depth;
+        let expr = match operation {
+            Operation::Custom(op) => Expression::CustomOperation((op.clone(), vec![operand])),
+            Operation::Unary(op) => Expression::UnaryOperation((op, Box::new(operand))),
+            _ => unreachable!(),
+        };
+        Ok(Some(iter.node(first, expr)))
+    }
+    // the operator `token` is when it follows an operand
+    fn ast_infix(&self, token: &Token) -> Option<Operation<'_>> {
+        use crate::Token::*;
+        let op = match token {
+            Symbol(symbol) => return self.operators.following(symbol).map(Operation::Custom),
+            Fact => return Some(Operation::Fact("fact")),
+            DoubleFact => return Some(Operation::Fact("dfact")),
+            Superscript(num) => return Some(Operation::Superscript(*num)),
+            Or => Operator::Or,
+            And => Operator::And,
+            Eq => Operator::Eq,
+            Ne => Operator::Ne,
+            Lt => Operator::Lt,
+            Le => Operator::Le,
+            Gt => Operator::Gt,
+            Ge => Operator::Ge,
+            BitOr => Operator::BitOr,
+            Xor => Operator::BitXor,
+            BitAnd => Operator::BitAnd,
+            Shl => Operator::Shl,
+            Shr => Operator::Shr,
+            Add => Operator::Add,
+            Sub => Operator::Sub,
+            Mult => Operator::Mult,
+            Div => Operator::Div,
+            Mod => Operator::Mod,
+            IntDiv => Operator::IntDiv,
+            Pow => Operator::Pow,
+            _ => return None,
+        };
+        Some(Operation::Binary(op))
+    }
+    // a juxtaposed operand, nothing is consumed
+    fn ast_implicit(&self, iter: &mut Cursor) -> Result<Option<Operation<'_>>, &'static str> {
+        let operand = self.ast_implicit_operand(iter);
+        match self.config.implicit_multiplication {
+            ImplicitMultiplication::Off => {
+                // only `2x` and `(x+1)(x-1)` get the hint, `a b` keeps the statement error
+                let end = iter.current().map(|tnk| tnk.1.end);
+                if end.is_some() && operand == end {
+                    return Err("Implicit multiplication is disabled, use '*'");
                 }
-            };
+                Ok(None)
+            }
+            ImplicitMultiplication::AsProduct => Ok(operand.map(|_| Operation::Implicit(operators::MULTIPLICATIVE))),
+            ImplicitMultiplication::Grouped => Ok(operand.map(|_| Operation::Implicit(operators::IMPLICIT))),
         }
-        Ok(left)
+    }
+    // where the juxtaposed operand starting at the next token begins, nothing is consumed
+    fn ast_implicit_operand(&self, iter: &mut Cursor) -> Option<usize> {
+        let start = iter.checkpoint();
+        let operand = match iter.next() {
+            Some((Token::Identifier(_), span)) | Some((Token::LParent, span)) => Some(span.start),
+            Some((Token::Sqrt, span)) | Some((Token::AbsOpen, span)) => Some(span.start),
+            _ => None,
+        };
+        iter.rewind(start);
+        operand
     }
 
-    fn ast_exponential_expression(&self, iter: &mut Iter<(Token, usize, usize)>) -> Result<Option<Expression>, &str> {
+    fn ast_arguments(&self, iter: &mut Cursor) -> Result<Vec<Expression>, &'static str> {
         use crate::Token::*;
-        use crate::Expression::*;
-        let left = self.ast_primitive(iter)?;
-        if left.is_none() {
-            return Ok(None);
+        match iter.next() {
+            Some((LParent, _)) => {}
+            Some(_) => return Err("Function expected '('"),
+            None => return Err("Function expected '('"),
         }
-        if let Some(tnk) = iter.next() {
-            match tnk.0 {
-                Pow => {
-                    let right = self.ast_primitive(iter)?;
-                    if right.is_none() {
-                        return Err("ERROR 4");
-                    }
-                    return Ok(Some(ExpressionOperation((Box::new(left.unwrap()), '^', Box::new(right.unwrap())))));
-                }
-                _ => {
-                    iter.prev();
+        let mut args = Vec::new();
+        loop {
+            let start = iter.checkpoint();
+            let arg = self.ast_expression(iter).and_then(|arg| arg.ok_or("Function must have input"));
+            let arg = arg.or_else(|error| self.recover(iter, start, error, |token| matches!(token, Comma | RParent)))?;
+            args.push(arg);
+            match iter.next() {
+                Some((Comma, _)) => {}
+                Some((RParent, _)) => return Ok(args),
+                _ => return Err("Function expected ')'"),
+            }
+        }
+    }
+
+    fn ast_piecewise(&self, iter: &mut Cursor) -> Result<Expression, &'static str> {
+        use crate::Token::*;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            if let Some((RBrace, _)) = iter.next() {
+                break;
+            }
+            iter.prev();
+            if otherwise.is_some() {
+                return Err("Piecewise 'otherwise' must be the last branch");
+            }
+            let expr = self.ast_expression(iter)?.ok_or("Piecewise expected an expression")?;
+            match iter.next() {
+                Some((If, _)) => {
+                    let condition = self.ast_expression(iter)?.ok_or("Piecewise expected a condition after 'if'")?;
+                    branches.push((condition, expr));
                 }
-            };
+                Some((Otherwise, _)) => otherwise = Some(Box::new(expr)),
+                _ => return Err("Piecewise expected 'if' or 'otherwise'"),
+            }
+            match iter.next() {
+                Some((Semicolon, _)) => {}
+                Some((RBrace, _)) => break,
+                _ => return Err("Piecewise expected ';' or '}'"),
+            }
+        }
+        if branches.is_empty() && otherwise.is_none() {
+            return Err("Piecewise expected an expression");
         }
-        match left.unwrap() {
-            Primitive(left) => Ok(Some(Primitive(left))),
-            ExpressionOperation(left) => Ok(Some(ExpressionOperation(left)))
+        Ok(Expression::Piecewise((branches, otherwise)))
+    }
+
+    // a call of the registered function `|x|`, `√`, `!` or `!!` stands for, so
+    // they follow an override of it as the call written out does
+    fn builtin(&self, name: &str, args: Vec<Expression>) -> Result<Expression, &'static str> {
+        let func = *self.functions.get(name).ok_or("Unknown function")?;
+        if args.len() != func.arity() {
+            return Err("Function called with wrong number of arguments");
         }
+        Ok(Expression::Primitive(Primitive::Function((name.to_string(), func, args))))
     }
 
-    fn ast_primitive(&self, iter: &mut Iter<(Token, usize, usize)>) -> Result<Option<Expression>, &str> {
+    fn ast_primitive(&self, iter: &mut Cursor) -> Result<Option<Expression>, &'static str> {
         use crate::Token::*;
-        return if let Some(tnk) = iter.next() {
+        let first = iter.next_index();
+        if let Some(tnk) = iter.next() {
             match &tnk.0 {
                 Identifier(ident) => {
-                    if ident.as_str() == "x" {
-                        return Ok(Some(Expression::Primitive(Primitive::Identifier(ident.clone()))));
-                    }
-                    return match self.functions.get(&ident.as_str()) {
-                        None => Err("ERROR 3"),
-                        Some(func) => {
-                            return match iter.next() {
-                                Some(token) => {
-                                    match token.0 {
-                                        LParent => {}
-                                        _ => {
-                                            return Err("ERROR FUNCTION MUST HAVE OPEN BRACKET");
-                                        }
-                                    }
-                                    let w_input = self.ast_additive_expression(iter)?;
-                                    match w_input {
-                                        None => Err("Function must have input"),
-                                        Some(input) => {
-                                            match iter.next() {
-                                                Some(token) => {
-                                                    match token.0 {
-                                                        RParent => {}
-                                                        _ => return Err("Function expected ')'")
-                                                    }
-                                                }
-                                                None => return Err("Function expected ')'")
-                                            }
-                                            Ok(Some(Expression::Primitive(Primitive::Function((func.clone(), Box::new(input))))))
-                                        }
-                                    }
-                                }
-                                None => Err("Function expected '('"),
-                            };
+                    let func = match self.functions.get(ident.as_str()) {
+                        Some(func) => *func,
+                        None => {
+                            // user-defined call, checked once the statement is parsed
+                            if let Some((LParent, _)) = iter.next() {
+                                iter.prev();
+                                let args = self.ast_arguments(iter)?;
+                                return Ok(Some(iter.node(first, Expression::Primitive(Primitive::Call((ident.clone(), args))))));
+                            }
+                            iter.prev();
+                            return Ok(Some(iter.node(first, Expression::Primitive(Primitive::Identifier(ident.clone())))));
                         }
                     };
+                    let args = self.ast_arguments(iter)?;
+                    if args.len() != func.arity() {
+                        return Err("Function called with wrong number of arguments");
+                    }
+                    Ok(Some(iter.node(first, Expression::Primitive(Primitive::Function((ident.clone(), func, args))))))
                 }
                 Number(num) => {
-                    return Ok(Some(Expression::Primitive(Primitive::Number(num.clone()))));
+                    Ok(Some(iter.node(first, Expression::Primitive(Primitive::Number(*num)))))
+                }
+                If => {
+                    let args = self.ast_arguments(iter)?;
+                    let [condition, then, otherwise]: [Expression; 3] = args.try_into().map_err(|_| "if expects 3 arguments")?;
+                    Ok(Some(iter.node(first, Expression::Conditional((Box::new(condition), Box::new(then), Box::new(otherwise))))))
                 }
                 LParent => {
-                    let expr = self.ast_additive_expression(iter)?;
-                    return match expr {
-                        Some(expr) => {
-                            match iter.next() {
-                                Some(token) => {
-                                    match token.0 {
-                                        RParent => {}
-                                        _ => return Err("Parenthesis expected ')'")
-                                    }
-                                }
-                                None => return Err("Parenthesis expected ')'")
-                            }
-                            Ok(Some(expr))
-                        }
-                        None => {
-                            match iter.next() {
-                                Some(token) => {
-                                    match token.0 {
-                                        RParent => Ok(None),
-                                        _ => return Err("Parenthesis expected ')'")
-                                    }
-                                }
-                                None => return Err("Parenthesis expected ')'")
-                            }
-                        }
+                    let start = iter.checkpoint();
+                    let expr = match self.ast_expression(iter) {
+                        Ok(expr) => expr,
+                        Err(error) => Some(self.recover(iter, start, error, |token| matches!(token, RParent))?),
                     };
+                    match iter.next() {
+                        Some((RParent, _)) => Ok(expr),
+                        _ => Err("Parenthesis expected ')'"),
+                    }
+                }
+                LBrace => {
+                    let expr = self.ast_piecewise(iter)?;
+                    Ok(Some(iter.node(first, expr)))
+                }
+                // reported while tokenizing
+                Unknown(_) => Ok(Some(iter.node(first, Expression::Error))),
+                AbsOpen => {
+                    let expr = self.ast_expression(iter)?.ok_or("Absolute value expected an expression")?;
+                    match iter.next() {
+                        Some((AbsClose, _)) => {}
+                        _ => return Err("Absolute value expected '|'"),
+                    }
+                    Ok(Some(iter.node(first, self.builtin("abs", vec![expr])?)))
+                }
+                Sqrt => {
+                    let depth = iter.depth;
+                    self.ast_deeper(iter)?;
+                    let operand = self.ast_operation(iter, operators::SQRT)?.ok_or("√ expected an operand")?;
+                    iter.depth = depth;
+                    Ok(Some(iter.node(first, self.builtin("sqrt", vec![operand])?)))
                 }
                 _ => {
-                    return Err("ERROR 1");
+                    Err("Expected an operand")
                 }
             }
         } else {
             Ok(None)
-        };
+        }
+    }
+}
+
+// an operator read by `ast_operation`
+enum Operation<'o> {
+    Binary(Operator),
+    Unary(UnaryOperator),
+    /// `!` and `!!`, the function they call
+    Fact(&'static str),
+    Superscript(f64),
+    /// A juxtaposed operand, at the precedence the mode gives it.
+    Implicit(u8),
+    Custom(&'o CustomOperator),
+}
+
+impl Operation<'_> {
+    fn precedence(&self) -> u8 {
+        match self {
+            Operation::Binary(op) => op.precedence(),
+            Operation::Unary(UnaryOperator::Neg) => operators::NEGATION,
+            Operation::Unary(UnaryOperator::Not) => operators::NOT,
+            Operation::Fact(..) | Operation::Superscript(_) => operators::POSTFIX,
+            Operation::Implicit(precedence) => *precedence,
+            Operation::Custom(op) => op.precedence,
+        }
+    }
+    fn associativity(&self) -> Associativity {
+        match self {
+            Operation::Binary(op) => op.associativity(),
+            Operation::Custom(CustomOperator { fixity: Fixity::Infix(associativity), .. }) => *associativity,
+            _ => Associativity::Left,
+        }
     }
 }
 
@@ -543,7 +2668,7 @@ mod numeric_tests {
     fn number_float() {
         let mut tokenizer = Tokenizer::new(" 10.1");
         let token = tokenizer.next().unwrap().unwrap().0;
-        println!("{}", token.to_string());
+        println!("{}", token);
         assert_eq!(token.typ(), "Number");
     }
 
@@ -564,6 +2689,57 @@ mod numeric_tests {
         let mut tokenizer = Tokenizer::new(" 10.1.1");
         assert_eq!(tokenizer.next().err().unwrap(), "Unexpected number");
     }
+
+    fn value(source: &str) -> f64 {
+        match Tokenizer::new(source).next().unwrap().unwrap().0 {
+            Token::Number(num) => num,
+            token => panic!("{} is not a number", token),
+        }
+    }
+
+    fn error(source: &str) -> &'static str {
+        Tokenizer::new(source).next().err().unwrap()
+    }
+
+    #[test]
+    fn number_exponent() {
+        assert_eq!(value("6.022e23"), 6.022e23);
+        assert_eq!(value("1e-9"), 1e-9);
+        assert_eq!(value("2.5E+3"), 2500.0);
+        assert_eq!(value("1_000e1_0"), 1e13);
+        assert_eq!(value(".5"), 0.5);
+        assert_eq!(value("1."), 1.0);
+        assert_eq!(value("1_000.000_5"), 1000.0005);
+        // 2exp(1) keeps the name
+        let mut tokenizer = Tokenizer::new("2exp");
+        assert_eq!(tokenizer.next().unwrap().unwrap().1.end, 1);
+        // an e without digits after it is a name too
+        for source in ["2e", "2e+x", "2E- 1"] {
+            assert_eq!(Tokenizer::new(source).next().unwrap().unwrap(), (Token::Number(2.0), Span { start: 0, end: 1, char_start: 0, char_end: 1 }));
+        }
+    }
+
+    #[test]
+    fn number_radix() {
+        assert_eq!(value("0x1F"), 31.0);
+        assert_eq!(value("0b1010"), 10.0);
+        assert_eq!(value("0o17"), 15.0);
+        assert_eq!(value("0xFF_FF"), 65535.0);
+    }
+
+    #[test]
+    fn number_malformed() {
+        assert_eq!(error("0123"), "Leading zeros are not allowed in a number");
+        assert_eq!(error("1e999"), "Number is too large");
+        assert_eq!(error("0x"), "Number expected digits after the radix prefix");
+        assert_eq!(error("0b102"), "Invalid digit for the number base");
+        assert_eq!(error("0x1.8"), "Only decimal numbers can have a fraction");
+        assert_eq!(error("1__0"), "Underscore must be between digits");
+        assert_eq!(error("1_"), "Underscore must be between digits");
+        assert_eq!(error("1._5"), "Underscore must be between digits");
+        let moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("x + 0b2").err().unwrap(), "Invalid digit for the number base");
+    }
 }
 
 #[cfg(test)]
@@ -606,9 +2782,9 @@ mod expr_tests {
         let b = tokenizer.next().unwrap().unwrap().0;
         let c = tokenizer.next().unwrap().unwrap().0;
 
-        println!("{}", a.to_string());
-        println!("{}", b.to_string());
-        println!("{}", c.to_string());
+        println!("{}", a);
+        println!("{}", b);
+        println!("{}", c);
 
         assert_eq!(a.typ(), "Number");
         assert_eq!(b.typ(), "Operator");
@@ -632,7 +2808,7 @@ mod parse_tests {
     
     #[test]
     fn parse_1() {
-        use std::time::{Duration, SystemTime};
+        use std::time::SystemTime;
         let moo = Moo::new(|functions| {
             functions.insert("relu", |v| {
                 f64::max(0.0, v)
@@ -649,5 +2825,586 @@ mod parse_tests {
             }
         }
         println!("{}", program.run(1000.0));
+        assert_eq!(moo.parse("relu(x - 2) + relu(x)").ok().unwrap().unwrap().run(1.0), 1.0);
+    }
+
+    #[test]
+    fn parse_special_module() {
+        let mut moo = Moo::new(|_| {});
+        moo.register(special::register);
+        let program = moo.parse("beta(2, 3) * gamma(x)").ok().unwrap().unwrap();
+        assert!((program.run(5.0) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn parse_function_arity() {
+        let mut moo = Moo::new(|_| {});
+        moo.register(special::register_gamma);
+        assert_eq!(moo.parse("beta(2)").err().unwrap(), "Function called with wrong number of arguments");
+        assert_eq!(moo.parse("gamma(1, 2)").err().unwrap(), "Function called with wrong number of arguments");
+    }
+
+    #[test]
+    fn parse_factorial() {
+        let mut moo = Moo::new(|_| {});
+        moo.register(special::register_combinatorics);
+        assert_eq!(moo.parse("3! + x").ok().unwrap().unwrap().run(1.0), 7.0);
+        assert_eq!(moo.parse("2 ^ 3!").ok().unwrap().unwrap().run(0.0), 64.0);
+        assert_eq!(moo.parse("7!!").ok().unwrap().unwrap().run(0.0), 105.0);
+        assert_eq!(moo.parse("nCr(x, 2) * fact(3)").ok().unwrap().unwrap().run(5.0), 60.0);
+        let program = moo.parse("(0 - x)!").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(3.0), Err("Factorial of a negative integer"));
+        assert!(program.run(3.0).is_nan());
+        assert!((program.run(-0.5) - 0.886_226_925_452_758).abs() < 1e-13);
+    }
+
+    #[test]
+    fn parse_associativity() {
+        let moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("1 - 2 - 3").ok().unwrap().unwrap().run(0.0), -4.0);
+        assert_eq!(moo.parse("2 * 3 * 4 / 8").ok().unwrap().unwrap().run(0.0), 3.0);
+        assert_eq!(moo.parse("2 ^ 3 ^ 2").ok().unwrap().unwrap().run(0.0), 512.0);
+    }
+
+    #[test]
+    fn parse_modulo() {
+        let moo = Moo::new(|_| {});
+        let run = |source: &str| moo.parse(source).ok().unwrap().unwrap().run(0.0);
+        assert_eq!(run("7 % 3"), 1.0);
+        assert_eq!(run("(0 - 7) % 3"), 2.0);
+        assert_eq!(run("7 % (0 - 3)"), -2.0);
+        assert_eq!(run("7.5 % 2"), 1.5);
+        assert_eq!(run("7 // 2"), 3.0);
+        assert_eq!(run("(0 - 7) // 2"), -4.0);
+        assert_eq!(run("1 + 7 // 2 * 2"), 7.0);
+        assert!(run("1 % 0").is_nan());
+    }
+
+    #[test]
+    fn parse_bitwise() {
+        let mut moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("6 & 3").err().unwrap(), "Bitwise operators need integer mode");
+        moo.configure(|config| config.integer_mode = true);
+        let run = |source: &str| moo.parse(source).ok().unwrap().unwrap().try_run(0.0);
+        assert_eq!(run("6 & 3"), Ok(2.0));
+        assert_eq!(run("6 | 3"), Ok(7.0));
+        assert_eq!(run("6 xor 3"), Ok(5.0));
+        assert_eq!(run("1 | 6 xor 3 & 1"), Ok(7.0));
+        assert_eq!(run("1 + 2 << 1"), Ok(6.0));
+        assert_eq!(run("(0 - 16) >> 2"), Ok(-4.0));
+        assert_eq!(run("(0 - 1) & 255"), Ok(255.0));
+        assert_eq!(run("1.5 & 1"), Err("Bitwise operand must be an integer"));
+        assert_eq!(run("1 << 64"), Err("Shift amount out of range"));
+        assert_eq!(run("1 << 63"), Err("Shift overflow"));
+    }
+
+    #[test]
+    fn parse_negation() {
+        let moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("-x ^ 2").ok().unwrap().unwrap().run(3.0), -9.0);
+        assert_eq!(moo.parse("2 ^ -1").ok().unwrap().unwrap().run(0.0), 0.5);
+        assert_eq!(moo.parse("2 * -x").ok().unwrap().unwrap().run(3.0), -6.0);
+    }
+
+    #[test]
+    fn parse_comparison() {
+        let mut moo = Moo::new(|_| {});
+        let program = moo.parse("x > 0").ok().unwrap().unwrap();
+        assert_eq!(program.run(1.0), 1.0);
+        assert_eq!(program.run(-1.0), 0.0);
+        assert_eq!(moo.parse("0.1 + 0.2 == 0.3").ok().unwrap().unwrap().run(0.0), 1.0);
+        assert_eq!(moo.parse("0.1 + 0.2 < 0.3").ok().unwrap().unwrap().run(0.0), 0.0);
+        assert_eq!(moo.parse("not x >= 1 or x == 5").ok().unwrap().unwrap().run(5.0), 1.0);
+        assert_eq!(moo.parse("1 < x < 2").err().unwrap(), "Comparison operators cannot be chained");
+        moo.configure(|config| config.tolerance = 0.0);
+        assert_eq!(moo.parse("0.1 + 0.2 == 0.3").ok().unwrap().unwrap().run(0.0), 0.0);
+        assert_eq!(moo.parse("0.1 + 0.2 != 0.3").ok().unwrap().unwrap().run(0.0), 1.0);
+    }
+
+    #[test]
+    fn parse_short_circuit() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("x > 0 and (0 - 1)! > 0").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(-1.0), Ok(0.0));
+        assert_eq!(program.try_run(1.0), Err("Factorial of a negative integer"));
+        let program = moo.parse("x > 0 or (0 - 1)! > 0").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(1.0), Ok(1.0));
+    }
+
+    #[test]
+    fn parse_conditional() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("x < 0 ? -1 : x == 0 ? 0 : 1").ok().unwrap().unwrap();
+        assert_eq!(program.run(-4.0), -1.0);
+        assert_eq!(program.run(0.0), 0.0);
+        assert_eq!(program.run(4.0), 1.0);
+        let program = moo.parse("if(x > 0, x, (0 - 1)!)").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(2.0), Ok(2.0));
+        assert_eq!(program.try_run(-2.0), Err("Factorial of a negative integer"));
+        assert_eq!(moo.parse("if(x, 1)").err().unwrap(), "if expects 3 arguments");
+        assert_eq!(moo.parse("x ? 1").err().unwrap(), "Conditional expected ':'");
+    }
+
+    #[test]
+    fn parse_piecewise() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("{ x ^ 2 if x < 0; sin(x) otherwise }").ok().unwrap().unwrap();
+        assert_eq!(program.run(-2.0), 4.0);
+        assert_eq!(program.run(0.0), 0.0);
+        let program = moo.parse("{ -1 if x < 0; 1 if x > 0 }").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(0.0), Err("No piecewise branch matched"));
+        assert_eq!(moo.parse("{ 1 otherwise; 2 if x }").err().unwrap(), "Piecewise 'otherwise' must be the last branch");
+        assert_eq!(moo.parse("{ 1 if x").err().unwrap(), "Piecewise expected ';' or '}'");
+    }
+
+    #[test]
+    fn piecewise_warnings() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("{ -1 if x < 0; 1 if x > 1; 2 if x >= 2 }").ok().unwrap().unwrap();
+        assert_eq!(program.piecewise_warnings(-4.0, 4.0, 9), vec![
+            PiecewiseWarning::Gap { from: 0.0, to: 1.0 },
+            PiecewiseWarning::Overlap { branches: (1, 2), from: 2.0, to: 4.0 },
+        ]);
+        let program = moo.parse("{ -1 if x < 0; 1 otherwise }").ok().unwrap().unwrap();
+        assert!(program.piecewise_warnings(-4.0, 4.0, 100).is_empty());
+    }
+
+    #[test]
+    fn parse_definitions() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("f(t) = t^2 + 1; g(t) = f(t) * sin(t); g(x)").ok().unwrap().unwrap();
+        assert_eq!(program.definitions.len(), 2);
+        assert_eq!(program.run(0.5), 1.25 * f64::sin(0.5));
+        let program = moo.parse("h(a, b) = a - b; h(x, 1) + h(1, x)").ok().unwrap().unwrap();
+        assert_eq!(program.run(5.0), 0.0);
+        assert_eq!(moo.parse("f(t) = t; f(1, 2)").err().unwrap(), "Function called with wrong number of arguments");
+        assert_eq!(moo.parse("f(t) = g(t); f(x)").err().unwrap(), "Unknown function");
+        assert_eq!(moo.parse("f(t) = t + a; f(x)").err().unwrap(), "Unknown variable");
+        assert_eq!(moo.parse("sin(t) = t; sin(x)").err().unwrap(), "Cannot redefine a built-in function");
+        assert_eq!(moo.parse("f(t) = t").err().unwrap(), "Program expected an expression");
+    }
+
+    #[test]
+    fn parse_recursion() {
+        let mut moo = Moo::new(|_| {});
+        let program = moo.parse("f(n) = n <= 1 ? 1 : n * f(n - 1); f(x)").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(5.0), Ok(120.0));
+        assert_eq!(program.try_run(500.0), Err("Recursion depth exceeded"));
+        moo.configure(|config| config.max_call_depth = 1000);
+        let program = moo.parse("f(n) = n <= 1 ? 1 : n + f(n - 1); f(x)").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(300.0), Ok(45_150.0));
+        // the levels of the body count, not only the calls
+        let program = moo.parse("f(n) = n <= 0 ? 0 : -(-(-(-f(n - 1)))); f(x)").ok().unwrap().unwrap();
+        assert_eq!(program.try_run(150.0), Ok(0.0));
+        assert_eq!(program.try_run(250.0), Err("Recursion depth exceeded"));
+    }
+
+    #[test]
+    fn parse_let() {
+        let moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("let a = 3 in a * x").ok().unwrap().unwrap().run(2.0), 6.0);
+        assert_eq!(moo.parse("let a = x in let b = a * 2 in a + b").ok().unwrap().unwrap().run(2.0), 6.0);
+        // a function body does not see the let bindings of its caller
+        assert_eq!(moo.parse("f(t) = t + a; let a = 1 in f(x)").err().unwrap(), "Unknown variable");
+        assert_eq!(moo.parse("a * x").err().unwrap(), "Unknown variable");
+        assert_eq!(moo.parse("let a = 3 a").err().unwrap(), "let expected 'in'");
+    }
+
+    #[test]
+    fn parse_piecewise_definition() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("f(t) = { t^2 if t < 0; sin(t) if t > 1 }; f(x)").ok().unwrap().unwrap();
+        assert_eq!(program.run(-2.0), 4.0);
+        assert_eq!(program.piecewise_warnings(-1.0, 2.0, 4), vec![PiecewiseWarning::Gap { from: 0.0, to: 1.0 }]);
+    }
+
+    #[test]
+    fn parse_script() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("a = 2; b = a^2; b + x").ok().unwrap().unwrap();
+        assert_eq!(program.assignments.len(), 2);
+        assert_eq!(program.run(1.0), 5.0);
+        let mut env = Environment::new();
+        env.set("x", 3.0);
+        let evaluation = program.eval(&mut env).unwrap();
+        assert_eq!(evaluation.assignments, vec![("a".to_string(), 2.0), ("b".to_string(), 4.0)]);
+        assert_eq!(evaluation.value, 7.0);
+        assert_eq!(env.get("b"), Some(4.0));
+        // newlines separate statements outside of brackets only
+        let program = moo.parse("f(t) = t * 2\n\na = f(\n  x\n)\na = a + 1\n").ok().unwrap().unwrap();
+        assert_eq!(program.run(2.0), 5.0);
+        // a line ending in an operator goes on on the next line
+        assert_eq!(moo.parse("x +\n 1").ok().unwrap().unwrap().run(2.0), 3.0);
+        assert_eq!(moo.parse("a = x *\n\n  2 -\n1\na ^\n2").ok().unwrap().unwrap().run(2.0), 9.0);
+        assert_eq!(moo.parse("x\n+ 1").err().unwrap(), "Program expected an expression as the last statement");
+        assert_eq!(moo.parse("a = 1 b = 2").err().unwrap(), "Expected ';' or a newline between statements");
+        assert_eq!(moo.parse("b = a; a = 1; b").err().unwrap(), "Unknown variable");
+        assert_eq!(moo.parse("sin = 1; sin").err().unwrap(), "Cannot assign to a built-in function");
+        assert_eq!(moo.parse("f(t) = t + a; a = 1; f(a)").err().unwrap(), "Unknown variable");
+    }
+
+    #[test]
+    fn parse_in_environment() {
+        let moo = Moo::new(|_| {});
+        let mut env = Environment::new();
+        moo.parse("rate = 0.5").ok().unwrap().unwrap().eval(&mut env).unwrap();
+        let program = moo.parse_in("total = rate * 10; total + 1", &env).ok().unwrap().unwrap();
+        let evaluation = program.eval(&mut env).unwrap();
+        assert_eq!(evaluation.value, 6.0);
+        assert_eq!(env.get("total"), Some(5.0));
+        assert!(moo.parse("rate * 10").is_err());
+    }
+
+    #[test]
+    fn eval_bounded() {
+        use std::sync::Arc;
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("f(n) = n < 2 ? n : f(n - 1) + f(n - 2); a = f(x); a + 1").ok().unwrap().unwrap();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut env = Environment::new();
+        env.set("x", 10.0);
+        assert_eq!(program.eval_bounded(&mut env, 100_000, &cancel).map(|e| e.value), Ok(56.0));
+        env.set("x", 25.0);
+        assert_eq!(program.eval_bounded(&mut env, 100_000, &cancel), Err(BoundedError::BudgetExceeded));
+        assert_eq!(program.eval_bounded(&mut env, 0, &cancel), Err(BoundedError::BudgetExceeded));
+        let failing = moo.parse("sqrt(-1) + (-1)!").ok().unwrap().unwrap();
+        assert!(matches!(failing.eval_bounded(&mut env, 100, &cancel), Err(BoundedError::Failed(_))));
+        // another thread stops a run that would take far longer
+        env.set("x", 60.0);
+        let flag = cancel.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(program.eval_bounded(&mut env, u64::MAX, &cancel), Err(BoundedError::Cancelled));
+        canceller.join().unwrap();
+        // the interrupted assignment leaves the value of the first run
+        assert_eq!(env.get("a"), Some(55.0));
+    }
+
+    #[test]
+    fn eval_checked() {
+        let mut moo = Moo::new(|_| {});
+        moo.register(special::register);
+        fn check<'s>(moo: &Moo, source: &'s str, x: f64) -> (EvalErrorKind, String, &'s str) {
+            let program = moo.parse(source).ok().unwrap().unwrap();
+            let mut env = Environment::new();
+            env.set("x", x);
+            let error = program.eval_checked(&mut env).err().unwrap();
+            let span = error.span.unwrap();
+            (error.kind, error.operation, &source[span.start..span.end])
+        }
+        assert_eq!(check(&moo, "2 + 1 / (x - 2)", 2.0), (EvalErrorKind::DivisionByZero, "/".to_string(), "1 / (x - 2)"));
+        assert_eq!(check(&moo, "3 * sqrt(x - 5)", 1.0), (EvalErrorKind::Domain, "sqrt".to_string(), "sqrt(x - 5)"));
+        assert_eq!(check(&moo, "1 + (x - 3)!", 1.0), (EvalErrorKind::Domain, "fact".to_string(), "(x - 3)!"));
+        assert_eq!(check(&moo, "x * 10^x", 400.0), (EvalErrorKind::Overflow, "^".to_string(), "10^x"));
+        assert_eq!(check(&moo, "x + 1", f64::NAN), (EvalErrorKind::NaN, "x".to_string(), "x"));
+        assert_eq!(check(&moo, "{ 1 if x < 0 }", 1.0), (EvalErrorKind::Other, "piecewise".to_string(), "{ 1 if x < 0 }"));
+        // the node is found inside a definition called from an assignment
+        assert_eq!(check(&moo, "f(t) = 1 + 1 / t\na = f(x - 1)\na * 2", 1.0), (EvalErrorKind::DivisionByZero, "/".to_string(), "1 / t"));
+        let program = moo.parse("1 / x").ok().unwrap().unwrap();
+        let mut env = Environment::new();
+        assert_eq!(program.eval(&mut env).unwrap().value, f64::INFINITY);
+        let error = program.eval_checked(&mut env).err().unwrap();
+        assert_eq!(error.to_string(), "Division by zero in '/' at 0..5");
+        env.set("x", 4.0);
+        assert_eq!(program.eval_checked(&mut env).map(|e| e.value), Ok(0.25));
+        // every node has a span, also in recovering mode
+        fn spanned(spans: &Spans, node: &Expression) -> bool {
+            spans.get(node).is_some() && node.children().iter().all(|child| spanned(spans, child))
+        }
+        for source in ["f(a, b) = a * b²\ng = -f(x, 2)!\n{ g if x < 0; let c = √x in c otherwise }", "a = 1\nb = (a +) * sin(2", "x = 3"] {
+            let program = moo.parse_recovering(source).program.unwrap();
+            let spans = program.spans();
+            assert!(program.definitions.iter().all(|d| spanned(&spans, &d.body)));
+            assert!(program.assignments.iter().all(|a| spanned(&spans, &a.value)));
+            assert!(spanned(&spans, &program.body));
+        }
+    }
+
+    #[test]
+    fn parse_implicit_multiplication() {
+        let mut moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("2x").err().unwrap(), "Implicit multiplication is disabled, use '*'");
+        moo.configure(|config| config.implicit_multiplication = ImplicitMultiplication::AsProduct);
+        let run = |moo: &Moo, source: &str, x: f64| moo.parse(source).ok().unwrap().unwrap().run(x);
+        assert_eq!(run(&moo, "2x", 3.0), 6.0);
+        assert_eq!(run(&moo, "2x^2", 3.0), 18.0);
+        assert_eq!(run(&moo, "3(x+1)", 3.0), 12.0);
+        assert_eq!(run(&moo, "(x+1)(x-1)", 3.0), 8.0);
+        assert_eq!(run(&moo, "2 sin(x)", 3.0), 2.0 * f64::sin(3.0));
+        assert_eq!(run(&moo, "-2x + 1", 3.0), -5.0);
+        assert_eq!(run(&moo, "1/2x", 3.0), 1.5);
+        assert_eq!(run(&moo, "2e", 0.0), 2.0 * std::f64::consts::E);
+        assert_eq!(run(&moo, "2e + 1", 0.0), 2.0 * std::f64::consts::E + 1.0);
+        assert_eq!(run(&moo, "2e-1", 0.0), 0.2);
+        moo.configure(|config| config.implicit_multiplication = ImplicitMultiplication::Grouped);
+        assert_eq!(run(&moo, "1/2x", 4.0), 0.125);
+        assert_eq!(run(&moo, "2x^2 / 4x", 2.0), 1.0);
+        assert_eq!(moo.parse("a = 2\n3a").ok().unwrap().unwrap().run(0.0), 6.0);
+    }
+
+    #[test]
+    fn parse_abs_bars() {
+        let mut moo = Moo::new(|_| {});
+        let run = |moo: &Moo, source: &str, x: f64| moo.parse(source).ok().unwrap().unwrap().run(x);
+        assert_eq!(run(&moo, "|x - 3|", 1.0), 2.0);
+        assert_eq!(run(&moo, "||x| - 5|", -2.0), 3.0);
+        assert_eq!(run(&moo, "|x| * |x - 4| + |-x|!", -1.0), 6.0);
+        assert_eq!(run(&moo, "(|x|) ^ 2", -3.0), 9.0);
+        assert_eq!(run(&moo, "||x||", -3.0), 3.0);
+        assert_eq!(run(&moo, "||x| - 1|", 0.5), 0.5);
+        assert_eq!(run(&moo, "2 * ||x|| + 1", -1.0), 3.0);
+        assert_eq!(moo.parse("|a|b|c|").err().unwrap(), "Ambiguous '|', write abs() or add parentheses");
+        assert_eq!(moo.parse("|x| |x|").err().unwrap(), "Ambiguous '|', write abs() or add parentheses");
+        assert_eq!(moo.parse("|x + 1").err().unwrap(), "Absolute value expected '|'");
+        moo.configure(|config| config.implicit_multiplication = ImplicitMultiplication::AsProduct);
+        assert_eq!(run(&moo, "2|x|", -3.0), 6.0);
+        moo.configure(|config| config.integer_mode = true);
+        assert_eq!(run(&moo, "|x - 8| | 1", 2.0), 7.0);
+        assert_eq!(run(&moo, "|(x | 1)|", 2.0), 3.0);
+        assert_eq!(run(&moo, "||x||", -3.0), 3.0);
+        assert_eq!(run(&moo, "||x| - 1|", 0.5), 0.5);
+        assert_eq!(moo.parse("|x | 1|").err().unwrap(), "Ambiguous '|', write abs() or add parentheses");
+    }
+
+    #[test]
+    fn parse_comments() {
+        let moo = Moo::new(|_| {});
+        let source = "# circle\nr = x /* radius */ * 2 # doubled\n/* area\n   of the circle */ r ^ 2";
+        assert_eq!(moo.parse(source).ok().unwrap().unwrap().run(1.5), 9.0);
+        assert_eq!(moo.parse("sin(x # angle\n)").ok().unwrap().unwrap().run(0.0), 0.0);
+        assert_eq!(moo.parse("x /* open").err().unwrap(), "Unterminated block comment");
+        assert_eq!(moo.parse("x // 2 /* floor */ + 1").ok().unwrap().unwrap().run(5.0), 3.0);
+        let mut tokenizer = Tokenizer::new("a /* é */\n# ü\nb");
+        assert_eq!(tokenizer.next().unwrap().unwrap().1.end, 1);
+        assert_eq!(tokenizer.next().unwrap().unwrap().1, Span { start: 10, end: 11, char_start: 9, char_end: 10 });
+        assert_eq!(tokenizer.next().unwrap().unwrap().1, Span { start: 16, end: 17, char_start: 14, char_end: 15 });
+    }
+
+    #[test]
+    fn parse_custom_operators() {
+        let mut moo = Moo::new(|_| {});
+        moo.add_operators(|operators| {
+            operators.insert_infix("**", operators::POWER, Associativity::Right, f64::powf);
+            operators.insert_infix("choose", operators::MULTIPLICATIVE, Associativity::Left, |n, k| {
+                special::binomial(n, k).unwrap_or(f64::NAN)
+            });
+            operators.insert_infix("<=>", operators::COMPARISON, Associativity::None, |a, b| {
+                (a - b).signum()
+            });
+            operators.insert_prefix("~", operators::NEGATION, |v| -v - 1.0);
+            operators.insert_postfix("°", operators::POSTFIX, f64::to_radians);
+            operators.insert_postfix("‰", operators::POSTFIX, |v| v / 1000.0);
+        });
+        let value = |source: &str| moo.parse(source).unwrap().unwrap().run(2.0);
+        assert_eq!(value("2 ** 3 ** 2"), 512.0);
+        assert_eq!(value("-x ** 2 * 3"), -12.0);
+        assert_eq!(value("5 choose x + 1"), 11.0);
+        assert_eq!(value("~x^2"), -5.0);
+        assert_eq!(value("90° * x"), std::f64::consts::PI);
+        assert_eq!(value("500‰ * x"), 1.0);
+        assert_eq!(value("x <=> 3 + 1"), -1.0);
+        assert_eq!(moo.parse("1 <=> 2 < 3").err().unwrap(), "Comparison operators cannot be chained");
+        assert_eq!(moo.parse("chooser").err().unwrap(), "Unknown variable");
+        let program = moo.parse("(2 ** 3) ** 2 + ~(x choose 2)°").ok().unwrap().unwrap();
+        assert_eq!(program.to_string(), "(2 ** 3) ** 2 + ~(x choose 2)°");
+        assert!((program.run(4.0) - (63.0 - 6f64.to_radians())).abs() < 1e-12);
+        let program = moo.parse("(2 ** 3) ** 2 + ~(x choose 2)‰").ok().unwrap().unwrap();
+        assert!((program.run(4.0) - 62.994).abs() < 1e-12);
+        // a built-in symbol is not taken over
+        let mut moo = Moo::new(|_| {});
+        moo.add_operators(|operators| {
+            operators.insert_postfix("%", operators::POSTFIX, |v| v / 100.0);
+            operators.insert_infix("and", operators::AND, Associativity::Left, f64::min);
+        });
+        assert_eq!(moo.parse("7 % 2").unwrap().unwrap().run(0.0), 1.0);
+        assert_eq!(moo.parse("2 and 0").unwrap().unwrap().run(0.0), 0.0);
+        assert_eq!(moo.parse("50%").err().unwrap(), "Operator expected an operand");
+    }
+
+    #[test]
+    fn parse_limits() {
+        let moo = Moo::new(|_| {});
+        let depth = moo.config.max_nesting_depth;
+        let shapes: [fn(usize) -> String; 8] = [
+            |n| format!("{}x{}", "(".repeat(n), ")".repeat(n)),
+            |n| format!("x{}", " + 1".repeat(n)),
+            |n| format!("{}x{}", "sin(".repeat(n), ")".repeat(n)),
+            |n| format!("{}x", "-".repeat(n)),
+            |n| format!("{}x", "√".repeat(n)),
+            |n| format!("{}1", "x ^ ".repeat(n)),
+            |n| format!("{}1", "let a = 1 in ".repeat(n)),
+            |n| format!("{}1", "x ? 1 : ".repeat(n)),
+        ];
+        // nested up to the limit parses, evaluates, prints, simplifies and drops
+        // without running out of a 2 MiB stack
+        let nested = std::thread::Builder::new().stack_size(2 << 20).spawn(move || {
+            let moo = Moo::new(|_| {});
+            for shape in shapes {
+                let program = moo.parse(&shape(depth - 1)).unwrap().unwrap();
+                program.run(0.5);
+                program.trace(&mut Environment::new());
+                moo.parse(&program.to_string()).unwrap();
+                program.bind("x", 0.5).simplify();
+                assert_eq!(moo.parse(&shape(depth)).err().unwrap(), "Expression is nested too deeply");
+            }
+            assert_eq!(moo.parse(&vec!["x"; 4999].join(" + ")).err().unwrap(), "Expression is nested too deeply");
+            // recursion through deeply nested bodies stops before the stack runs out
+            let source = format!("f(n) = n <= 0 ? 0 : {}f(n - 1){}; f(1000)", "(".repeat(depth / 2 - 2), " + 0)".repeat(depth / 2 - 2));
+            let program = moo.parse(&source).unwrap().unwrap();
+            let mut env = Environment::new();
+            assert_eq!(program.eval(&mut env).err(), Some("Recursion depth exceeded"));
+            assert_eq!(program.eval_checked(&mut env).err().unwrap().message, "Recursion depth exceeded");
+            assert_eq!(program.trace(&mut env).statements[0].1.value, Err("Recursion depth exceeded"));
+            assert_eq!(program.compile().try_run(0.0), Err("Recursion depth exceeded"));
+        });
+        nested.unwrap().join().unwrap();
+        assert_eq!(moo.parse(&"((".repeat(40_000)).err().unwrap(), "Expression is nested too deeply");
+        let mut moo = Moo::new(|_| {});
+        moo.configure(|config| {
+            config.max_source_length = 20;
+            config.max_tokens = 9;
+            config.max_nodes = 6;
+        });
+        assert_eq!(moo.parse("x + 1 /* a bit too long */").err().unwrap(), "Source is too long");
+        assert_eq!(moo.parse("x+x+x+x+x+x").err().unwrap(), "Too many tokens");
+        assert_eq!(moo.parse("-x * sin(x) + 2").err().unwrap(), "Expression has too many nodes");
+        assert_eq!(moo.parse("-x * sin(x)").unwrap().unwrap().run(1.0), -f64::sin(1.0));
+        assert_eq!(moo.parse_recovering("x+x+x+x+x+x").diagnostics[0], "Too many tokens");
+        assert_eq!(moo.parse_cst("x+x+x+x+x+x").err().unwrap(), "Too many tokens");
+    }
+
+    #[test]
+    fn parse_recovering() {
+        let moo = Moo::new(|_| {});
+        let recovery = moo.parse_recovering("a = 1 +;\nb = sin(1 +)\nf(x) = x $ 1; a + (*) + b");
+        let messages: Vec<&str> = recovery.diagnostics.iter().map(|error| error.message).collect();
+        assert_eq!(messages, vec!["Expected an operand", "Expected an operand", "Unexpected character", "Expected an operand"]);
+        assert_eq!(recovery.diagnostics[0].span.unwrap().start, 7);
+        let program = recovery.program.unwrap();
+        assert_eq!(program.to_string(), "f(x) = x; a = <error>; b = sin(<error>); a + <error> + b");
+        assert_eq!(program.try_run(0.0), Err("Program has syntax errors"));
+        let recovery = moo.parse_recovering("1 + 0b2; x 2; y");
+        let messages: Vec<&str> = recovery.diagnostics.iter().map(|error| error.message).collect();
+        assert_eq!(messages, vec!["Invalid digit for the number base", "Program expected an expression as the last statement", "Expected ';' or a newline between statements", "Program expected an expression as the last statement"]);
+        assert_eq!(recovery.program.unwrap().run(3.0), 0.0);
+        let recovery = moo.parse_recovering("sin(x) + 1");
+        assert!(recovery.diagnostics.is_empty());
+        assert_eq!(recovery.program.unwrap().run(0.0), 1.0);
+        assert!(moo.parse_recovering("").program.is_none());
+        // an unclosed bracket ends at the line break, the next lines still parse
+        let recovery = moo.parse_recovering("a = 1\nb = ( \nc = 3\nd = |c + 1\nf = sin(x +\n  c) + d; f");
+        let messages: Vec<&str> = recovery.diagnostics.iter().map(|error| error.message).collect();
+        assert_eq!(messages, vec!["Expected an operand", "Absolute value expected '|'"]);
+        assert_eq!(recovery.diagnostics[0].span.unwrap().start, 12);
+        assert_eq!(recovery.program.unwrap().to_string(), "a = 1; b = <error>; c = 3; d = <error>; f = sin(x + c) + d; f");
+        // past the last token the error is at the end of the source
+        // an ambiguous bar is reported at the bar
+        let recovery = moo.parse_recovering("a = 1\n|a|b|c|");
+        assert_eq!(recovery.diagnostics[0].message, "Ambiguous '|', write abs() or add parentheses");
+        assert_eq!(recovery.diagnostics[0].span.unwrap().start, 8);
+        let recovery = moo.parse_recovering("x + ");
+        let span = recovery.diagnostics[0].span.unwrap();
+        assert_eq!((span.start, span.end, span.char_start), (4, 4, 4));
+        let recovery = moo.parse_recovering("π = (1 +");
+        assert_eq!(recovery.diagnostics[0].span.unwrap().char_start, 8);
+    }
+}
+
+#[cfg(test)]
+mod unicode_tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<(Token, Span)> {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut tokens = Vec::new();
+        while let Ok(Some(token)) = tokenizer.next() {
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    #[test]
+    fn unicode_identifier() {
+        let tokens = tokens("วัว + x");
+        assert_eq!(tokens[0].0.to_string(), "วัว");
+        assert_eq!(tokens[0].1, Span { start: 0, end: 9, char_start: 0, char_end: 3 });
+        assert_eq!(tokens[2].1, Span { start: 12, end: 13, char_start: 6, char_end: 7 });
+    }
+
+    #[test]
+    fn unicode_operators() {
+        let moo = Moo::new(|_| {});
+        let run = |source: &str, x: f64| moo.parse(source).ok().unwrap().unwrap().run(x);
+        assert_eq!(run("6 × x ÷ 4 − 1", 2.0), 2.0);
+        assert_eq!(run("x·x²", 3.0), 27.0);
+        assert_eq!(run("x³ + x¹⁰", 1.0), 2.0);
+        assert_eq!(run("-x²", 3.0), -9.0);
+        assert_eq!(run("√x + √(x + 5)", 4.0), 5.0);
+        assert_eq!(run("√x²", -3.0), 3.0);
+        assert_eq!(run("π", 0.0), f64::consts::PI);
+        assert_eq!(run("x ≤ 1 and x ≥ 0 and x ≠ 0.5", 1.0), 1.0);
+        assert_eq!(run("let π = 3 in π", 0.0), 3.0);
+        assert_eq!(tokens("πr²").len(), 3);
+    }
+
+    #[test]
+    fn unicode_normalize() {
+        assert_eq!(normalize("2×π·r²"), "2*pi*r^2");
+        assert_eq!(normalize("a − b ÷ c"), "a - b / c");
+        assert_eq!(normalize("√x + √(x+1) + √√y! + 2√sin(x)"), "sqrt(x) + sqrt(x+1) + sqrt(sqrt(y!)) + 2 sqrt(sin(x))");
+        assert_eq!(normalize("xπ ≤ 1_000\u{a0}≥ x¹⁰"), "x pi <= 1_000 >= x^10");
+        assert_eq!(normalize("วัว × 2"), "วัว * 2");
+        let moo = Moo::new(|_| {});
+        let program = moo.parse(&normalize("√x·π")).ok().unwrap().unwrap();
+        assert_eq!(program.run(4.0), 2.0 * f64::consts::PI);
+    }
+
+    #[test]
+    fn unicode_whitespace() {
+        // U+00A0 no-break space and U+3000 ideographic space
+        let tokens = tokens("1\u{a0}+\u{3000}x");
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].1, Span { start: 3, end: 4, char_start: 2, char_end: 3 });
+        assert_eq!(tokens[2].1, Span { start: 7, end: 8, char_start: 4, char_end: 5 });
+        let moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("2\u{a0}*\u{a0}x").ok().unwrap().unwrap().run(3.0), 6.0);
+    }
+
+    // xorshift, the same sequence on every run
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    #[test]
+    fn unicode_fuzz() {
+        let alphabet: Vec<char> = "x1._e0b( )+-*/^!=<>;,{}|&#\n\u{a0}\u{3000}\u{2028}วัé\u{301}€😀×−π²\u{feff}\u{0}"
+            .chars()
+            .collect();
+        let moo = Moo::new(|_| {});
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5000 {
+            let length = random.next(12);
+            let source: String = (0..length).map(|_| alphabet[random.next(alphabet.len())]).collect();
+            let mut end = 0;
+            for (_, span) in tokens(&source) {
+                assert!(source.is_char_boundary(span.start) && source.is_char_boundary(span.end), "{:?}", source);
+                assert!(span.start >= end && span.start <= span.end);
+                assert_eq!(source[..span.start].chars().count(), span.char_start);
+                assert_eq!(source[span.start..span.end].chars().count(), span.char_end - span.char_start);
+                end = span.end;
+            }
+            if let Ok(Some(program)) = moo.parse(&source) {
+                program.run(1.0);
+            }
+        }
     }
 }
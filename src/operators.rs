@@ -0,0 +1,148 @@
+// operator table
+// precedences of the built-in operators and the operators an embedder adds,
+// the parser reads every operator by binding power from here
+
+use crate::{Func, Function, Function2, Operator, Token, Tokenizer};
+
+/// Precedence of `or`, operators with a higher precedence bind tighter.
+pub const OR: u8 = 10;
+pub const AND: u8 = 20;
+/// Prefix `not`, its operand takes comparisons but not `and`.
+pub const NOT: u8 = 30;
+/// `==`, `!=`, `<`, `<=`, `>` and `>=`, these do not chain.
+pub const COMPARISON: u8 = 40;
+pub const BIT_OR: u8 = 50;
+pub const BIT_XOR: u8 = 60;
+pub const BIT_AND: u8 = 70;
+/// `<<` and `>>`
+pub const SHIFT: u8 = 80;
+/// `+` and `-`
+pub const ADDITIVE: u8 = 90;
+/// `*`, `/`, `%`, `//` and implicit multiplication in
+/// [`ImplicitMultiplication::AsProduct`](crate::ImplicitMultiplication::AsProduct).
+pub const MULTIPLICATIVE: u8 = 100;
+/// Implicit multiplication in [`ImplicitMultiplication::Grouped`](crate::ImplicitMultiplication::Grouped).
+pub const IMPLICIT: u8 = 105;
+/// Prefix `-`, `-x^2 = -(x^2)`.
+pub const NEGATION: u8 = 110;
+/// `^`, right associative.
+pub const POWER: u8 = 120;
+/// Prefix `√`, `√x^2 = (√x)^2`.
+pub const SQRT: u8 = 130;
+/// `!`, `!!` and superscript powers.
+pub const POSTFIX: u8 = 140;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Associativity {
+    /// `a - b - c = (a - b) - c`
+    Left,
+    /// `a ^ b ^ c = a ^ (b ^ c)`
+    Right,
+    /// `a < b < c` is an error.
+    None,
+}
+
+/// Where a custom operator stands relative to its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Fixity {
+    Prefix,
+    Infix(Associativity),
+    Postfix,
+}
+
+/// An operator added with [`Moo::add_operators`](crate::Moo::add_operators),
+/// operators are equal when their symbol and fixity are.
+#[derive(Debug, Clone)]
+pub struct CustomOperator {
+    pub symbol: String,
+    pub fixity: Fixity,
+    pub precedence: u8,
+    pub function: Func,
+}
+
+/// Custom operators of a [`Moo`](crate::Moo).
+///
+/// A symbol is read before any built-in token, so `"**"` is one token and a
+/// word such as `"choose"` is no longer a name. A symbol can be both a prefix
+/// and an infix operator like `-`, but not both infix and postfix.
+///
+/// A symbol that is a built-in token on its own, such as `"%"`, `"and"` or
+/// `"²"`, is ignored so the built-in operator keeps its meaning.
+#[derive(Debug, Default)]
+pub struct Operators {
+    operators: Vec<CustomOperator>,
+    // every symbol, longest first so `**` is read before `*`
+    symbols: Vec<String>,
+}
+
+impl Operators {
+    pub fn insert_infix(&mut self, symbol: &str, precedence: u8, associativity: Associativity, function: Function2) {
+        self.insert(symbol, Fixity::Infix(associativity), precedence, Func::Binary(function));
+    }
+    pub fn insert_prefix(&mut self, symbol: &str, precedence: u8, function: Function) {
+        self.insert(symbol, Fixity::Prefix, precedence, Func::Unary(function));
+    }
+    pub fn insert_postfix(&mut self, symbol: &str, precedence: u8, function: Function) {
+        self.insert(symbol, Fixity::Postfix, precedence, Func::Unary(function));
+    }
+    fn insert(&mut self, symbol: &str, fixity: Fixity, precedence: u8, function: Func) {
+        // an empty symbol would be read at every position
+        if symbol.is_empty() || builtin(symbol) {
+            return;
+        }
+        // an infix and a postfix operator of the same symbol cannot be told apart
+        let prefix = fixity == Fixity::Prefix;
+        self.operators.retain(|op| op.symbol != symbol || (op.fixity == Fixity::Prefix) != prefix);
+        self.operators.push(CustomOperator { symbol: symbol.to_string(), fixity, precedence, function });
+        if !self.symbols.iter().any(|s| s == symbol) {
+            self.symbols.push(symbol.to_string());
+            self.symbols.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        }
+    }
+    pub(crate) fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+    pub(crate) fn prefix(&self, symbol: &str) -> Option<&CustomOperator> {
+        self.operators.iter().find(|op| op.symbol == symbol && op.fixity == Fixity::Prefix)
+    }
+    // the infix or postfix operator of `symbol`
+    pub(crate) fn following(&self, symbol: &str) -> Option<&CustomOperator> {
+        self.operators.iter().find(|op| op.symbol == symbol && op.fixity != Fixity::Prefix)
+    }
+}
+
+// whether `symbol` reads as a single built-in token
+fn builtin(symbol: &str) -> bool {
+    let mut tokenizer = Tokenizer::new(symbol);
+    match (tokenizer.next(), tokenizer.next()) {
+        (Ok(Some((Token::Identifier(_) | Token::Number(_) | Token::Unknown(_), _))), _) => false,
+        (Ok(Some(_)), Ok(None)) => true,
+        _ => false,
+    }
+}
+
+impl Operator {
+    pub(crate) fn precedence(&self) -> u8 {
+        use Operator::*;
+        match self {
+            Or => OR,
+            And => AND,
+            Eq | Ne | Lt | Le | Gt | Ge => COMPARISON,
+            BitOr => BIT_OR,
+            BitXor => BIT_XOR,
+            BitAnd => BIT_AND,
+            Shl | Shr => SHIFT,
+            Add | Sub => ADDITIVE,
+            Mult | Div | Mod | IntDiv => MULTIPLICATIVE,
+            Pow => POWER,
+        }
+    }
+    pub(crate) fn associativity(&self) -> Associativity {
+        use Operator::*;
+        match self {
+            Pow => Associativity::Right,
+            Eq | Ne | Lt | Le | Gt | Ge => Associativity::None,
+            _ => Associativity::Left,
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// printing
+// expressions print back to source text that parses to the same tree,
+// parentheses are only added where precedence requires them
+
+use crate::operators::{self, Associativity, Fixity};
+use crate::{Expression, Operator, Primitive, Program, UnaryOperator};
+use std::fmt::{Display, Formatter, Result};
+
+// binding strength, higher binds tighter
+const CONDITIONAL: u8 = 0;
+const PRIMITIVE: u8 = u8::MAX;
+
+impl Operator {
+    pub(crate) fn symbol(&self) -> &'static str {
+        use Operator::*;
+        match self {
+            Add => "+",
+            Sub => "-",
+            Mult => "*",
+            Div => "/",
+            Pow => "^",
+            Mod => "%",
+            IntDiv => "//",
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "xor",
+            Shl => "<<",
+            Shr => ">>",
+            Eq => "==",
+            Ne => "!=",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+            And => "and",
+            Or => "or",
+        }
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+impl Display for UnaryOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            UnaryOperator::Neg => write!(f, "-"),
+            UnaryOperator::Not => write!(f, "not "),
+        }
+    }
+}
+
+impl Expression {
+    fn precedence(&self) -> u8 {
+        use Expression::*;
+        match self {
+            Primitive(_) | Piecewise(_) | Error => PRIMITIVE,
+            ExpressionOperation(expr) => expr.1.precedence(),
+            UnaryOperation((UnaryOperator::Neg, _)) => operators::NEGATION,
+            UnaryOperation((UnaryOperator::Not, _)) => operators::NOT,
+            CustomOperation(expr) => expr.0.precedence,
+            Conditional(_) | Let(_) => CONDITIONAL,
+        }
+    }
+    fn fmt_operand(&self, f: &mut Formatter<'_>, parenthesize: bool) -> Result {
+        if parenthesize {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        use Expression::*;
+        match self {
+            Primitive(primitive) => write!(f, "{}", primitive),
+            ExpressionOperation(expr) => {
+                let (left, right) = operand_bounds(expr.1.precedence(), expr.1.associativity());
+                expr.0.fmt_operand(f, expr.0.precedence() <= left)?;
+                write!(f, " {} ", expr.1)?;
+                expr.2.fmt_operand(f, expr.2.precedence() <= right)
+            }
+            CustomOperation(expr) => {
+                let (op, args) = (&expr.0, &expr.1);
+                match op.fixity {
+                    // a word symbol is kept apart from the name next to it
+                    Fixity::Prefix if op.symbol.ends_with(char::is_alphanumeric) => {
+                        write!(f, "{} ", op.symbol)?;
+                        args[0].fmt_operand(f, args[0].precedence() < op.precedence)
+                    }
+                    Fixity::Prefix => {
+                        write!(f, "{}", op.symbol)?;
+                        args[0].fmt_operand(f, args[0].precedence() < op.precedence)
+                    }
+                    Fixity::Postfix => {
+                        args[0].fmt_operand(f, args[0].precedence() < op.precedence)?;
+                        if op.symbol.starts_with(char::is_alphanumeric) {
+                            write!(f, " ")?;
+                        }
+                        write!(f, "{}", op.symbol)
+                    }
+                    Fixity::Infix(associativity) => {
+                        let (left, right) = operand_bounds(op.precedence, associativity);
+                        args[0].fmt_operand(f, args[0].precedence() <= left)?;
+                        write!(f, " {} ", op.symbol)?;
+                        args[1].fmt_operand(f, args[1].precedence() <= right)
+                    }
+                }
+            }
+            UnaryOperation(expr) => {
+                write!(f, "{}", expr.0)?;
+                expr.1.fmt_operand(f, expr.1.precedence() < self.precedence())
+            }
+            Conditional(expr) => {
+                expr.0.fmt_operand(f, expr.0.precedence() == CONDITIONAL)?;
+                write!(f, " ? {} : {}", expr.1, expr.2)
+            }
+            Piecewise(expr) => {
+                write!(f, "{{ ")?;
+                for (i, (condition, branch)) in expr.0.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{} if {}", branch, condition)?;
+                }
+                if let Some(otherwise) = &expr.1 {
+                    if !expr.0.is_empty() {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{} otherwise", otherwise)?;
+                }
+                write!(f, " }}")
+            }
+            Let(expr) => write!(f, "let {} = {} in {}", expr.0, expr.1, expr.2),
+            Error => write!(f, "<error>"),
+        }
+    }
+}
+
+// operands of an infix operator at or below these precedences need parentheses,
+// `^` groups to the right and comparisons do not group at all
+fn operand_bounds(precedence: u8, associativity: Associativity) -> (u8, u8) {
+    match associativity {
+        Associativity::Left => (precedence.saturating_sub(1), precedence),
+        Associativity::Right => (precedence, precedence.saturating_sub(1)),
+        Associativity::None => (precedence, precedence),
+    }
+}
+
+impl Display for Primitive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        use Primitive::*;
+        match self {
+            Function(func) => fmt_call(f, &func.0, &func.2),
+            Call(call) => fmt_call(f, &call.0, &call.1),
+            Identifier(ident) => write!(f, "{}", ident),
+            Number(num) => write!(f, "{}", num),
+        }
+    }
+}
+
+fn fmt_call(f: &mut Formatter<'_>, name: &str, args: &[Expression]) -> Result {
+    write!(f, "{}(", name)?;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    write!(f, ")")
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for definition in self.definitions.iter() {
+            write!(f, "{}({}) = {}; ", definition.name, definition.params.join(", "), definition.body)?;
+        }
+        for assignment in self.assignments.iter() {
+            write!(f, "{} = {}; ", assignment.name, assignment.value)?;
+        }
+        write!(f, "{}", self.body)
+    }
+}
+
+#[cfg(test)]
+mod print_tests {
+    use crate::Moo;
+
+    fn print(source: &str) -> String {
+        let moo = Moo::new(|_| {});
+        moo.parse(source).ok().unwrap().unwrap().to_string()
+    }
+
+    #[test]
+    fn print_precedence() {
+        assert_eq!(print("1+2*x"), "1 + 2 * x");
+        assert_eq!(print("(1+2)*x"), "(1 + 2) * x");
+        assert_eq!(print("1-(2-3)"), "1 - (2 - 3)");
+        assert_eq!(print("(1-2)-3"), "1 - 2 - 3");
+        assert_eq!(print("(2^3)^2"), "(2 ^ 3) ^ 2");
+        assert_eq!(print("2^3^2"), "2 ^ 3 ^ 2");
+        assert_eq!(print("-x^2"), "-x ^ 2");
+        assert_eq!(print("(-x)^2"), "(-x) ^ 2");
+        assert_eq!(print("sin(x)!"), "fact(sin(x))");
+    }
+
+    #[test]
+    fn print_conditional() {
+        assert_eq!(print("x > 0 and not x == 3 ? 1 : 2"), "x > 0 and not x == 3 ? 1 : 2");
+        assert_eq!(print("(x ? 1 : 2) + 1"), "(x ? 1 : 2) + 1");
+        assert_eq!(print("if(x, 1, 2)"), "x ? 1 : 2");
+    }
+
+    #[test]
+    fn print_piecewise() {
+        assert_eq!(print("{x^2 if x<0; sin(x) otherwise}"), "{ x ^ 2 if x < 0; sin(x) otherwise }");
+        assert_eq!(print("{ 1 if x < 0; 2 if x >= 0; }"), "{ 1 if x < 0; 2 if x >= 0 }");
+    }
+
+    #[test]
+    fn print_definitions() {
+        assert_eq!(print("f(t)=t^2+1; g(a,b)=f(a)*b; g(x,2)"), "f(t) = t ^ 2 + 1; g(a, b) = f(a) * b; g(x, 2)");
+        assert_eq!(print("1 + (let a = 3 in a * x)"), "1 + (let a = 3 in a * x)");
+        assert_eq!(print("a = 2\nb = a^2\nb + x"), "a = 2; b = a ^ 2; b + x");
+    }
+}
@@ -0,0 +1,256 @@
+// rewriting programs
+// a variable replaced by an expression or a constant, and constant folding to
+// tidy up what that leaves
+
+use crate::{constant, truth, Config, Context, Definition, Expression, Operator, Primitive, Program, UnaryOperator};
+use std::collections::BTreeSet;
+
+impl Program {
+    /// The program with every free `var` replaced by `with`, substituting `x`
+    /// of `sin(x) + 1` with `x^2` gives `sin(x ^ 2) + 1`.
+    ///
+    /// The assignments of `with` become `let`s around its body and its function
+    /// definitions are added. Fails when a name `with` uses would be bound by a
+    /// parameter, assignment or `let` where it goes, when `with` uses a variable
+    /// other than `x` and `y` inside a function definition, or when both programs
+    /// define a function of the same name differently.
+    pub fn substitute(&self, var: &str, with: &Program) -> Result<Program, &'static str> {
+        let replacement = with.assignments.iter().rev().fold(with.body.clone(), |body, assignment| {
+            Expression::Let((assignment.name.clone(), Box::new(assignment.value.clone()), Box::new(body)))
+        });
+        let mut program = self.replaced(var, &replacement)?;
+        for definition in with.definitions.iter() {
+            match program.definitions.iter().find(|d| d.name == definition.name) {
+                Some(d) if d.params == definition.params && d.body == definition.body => {}
+                Some(_) => return Err("Function is already defined"),
+                None => program.definitions.push(definition.clone()),
+            }
+        }
+        Ok(program)
+    }
+    /// The program with every free `var` replaced by `value`, partial evaluation
+    /// when followed by [`Program::simplify`].
+    pub fn bind(&self, var: &str, value: f64) -> Program {
+        self.replaced(var, &number(value)).expect("a number uses no names")
+    }
+    /// Folds the operations on constants, `2 * 3 + x` becomes `6 + x`, takes the
+    /// branch of a constant condition, inlines `let`s of a constant and drops
+    /// `+ 0`, `- 0`, `* 1`, `/ 1` and `^ 1`.
+    ///
+    /// Functions are assumed to be pure, as in [`Program::compile`]. An operation
+    /// that fails or is not finite is kept so it still does when evaluated.
+    pub fn simplify(&mut self) {
+        let config = self.config;
+        self.definitions.iter_mut().for_each(|definition| definition.body.simplify(config));
+        self.assignments.iter_mut().for_each(|assignment| assignment.value.simplify(config));
+        self.body.simplify(config);
+        self.spans = Default::default();
+    }
+    fn replaced(&self, var: &str, with: &Expression) -> Result<Program, &'static str> {
+        let mut free = BTreeSet::new();
+        with.free_identifiers(&mut Vec::new(), &mut free);
+        let free: Vec<&str> = free.into_iter().collect();
+        // a function body only sees its parameters, `x`, `y` and the constants
+        let hidden = free.iter().any(|name| !matches!(*name, "x" | "y") && constant(name).is_none());
+        let mut program = self.clone();
+        program.spans = Default::default();
+        for Definition { params, body, .. } in program.definitions.iter_mut() {
+            if params.iter().any(|param| param == var) {
+                continue;
+            }
+            let bound = params.iter().filter(|param| free.contains(&param.as_str())).count();
+            if body.replace(var, with, &free, bound)? && hidden {
+                return Err("Unknown variable");
+            }
+        }
+        let mut bound = 0;
+        for assignment in program.assignments.iter_mut() {
+            assignment.value.replace(var, with, &free, bound)?;
+            // the statements after it see the assigned value
+            if assignment.name == var {
+                return Ok(program);
+            }
+            bound += free.contains(&assignment.name.as_str()) as usize;
+        }
+        program.body.replace(var, with, &free, bound)?;
+        Ok(program)
+    }
+}
+
+// a value as the parser builds it, a negative number is a negation
+fn number(value: f64) -> Expression {
+    let literal = Expression::Primitive(Primitive::Number(value.abs()));
+    if value.is_sign_negative() {
+        Expression::UnaryOperation((UnaryOperator::Neg, Box::new(literal)))
+    } else {
+        literal
+    }
+}
+
+impl Expression {
+    // replaces the free `var`, `bound` counts the names of `free` bound around
+    // this node, true when anything was replaced
+    fn replace(&mut self, var: &str, with: &Expression, free: &[&str], bound: usize) -> Result<bool, &'static str> {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Identifier(ident)) if ident == var => {
+                if bound > 0 {
+                    return Err("Substitution would capture a variable");
+                }
+                *self = with.clone();
+                Ok(true)
+            }
+            Let(expr) => {
+                let replaced = expr.1.replace(var, with, free, bound)?;
+                if expr.0 == var {
+                    return Ok(replaced);
+                }
+                let bound = bound + free.contains(&expr.0.as_str()) as usize;
+                Ok(expr.2.replace(var, with, free, bound)? || replaced)
+            }
+            _ => {
+                let mut replaced = false;
+                for child in self.children_mut() {
+                    replaced |= child.replace(var, with, free, bound)?;
+                }
+                Ok(replaced)
+            }
+        }
+    }
+    // the value of a number or a negated number
+    fn literal(&self) -> Option<f64> {
+        use Expression::*;
+        match self {
+            Primitive(crate::Primitive::Number(num)) => Some(*num),
+            UnaryOperation((UnaryOperator::Neg, expr)) => match **expr {
+                Primitive(crate::Primitive::Number(num)) => Some(-num),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    fn simplify(&mut self, config: Config) {
+        use Expression::*;
+        if let Let(expr) = self {
+            expr.1.simplify(config);
+            if expr.1.literal().is_none() {
+                return expr.2.simplify(config);
+            }
+            let mut body = std::mem::replace(&mut *expr.2, Error);
+            body.replace(&expr.0, &expr.1, &[], 0).expect("a number uses no names");
+            body.simplify(config);
+            *self = body;
+            return;
+        }
+        for child in self.children_mut() {
+            child.simplify(config);
+        }
+        let simplified = match self {
+            Conditional(expr) => match expr.0.literal() {
+                Some(condition) if truth(condition) => Some(std::mem::replace(&mut *expr.1, Error)),
+                Some(_) => Some(std::mem::replace(&mut *expr.2, Error)),
+                None => None,
+            },
+            Piecewise(expr) => {
+                // a branch whose condition is constant and false never matches
+                let never = |condition: &Expression| condition.literal().is_some_and(|value| !truth(value));
+                if expr.1.is_some() || !expr.0.iter().all(|(condition, _)| never(condition)) {
+                    expr.0.retain(|(condition, _)| !never(condition));
+                }
+                match expr.0.first() {
+                    Some((condition, _)) if condition.literal().is_some_and(truth) => Some(expr.0.swap_remove(0).1),
+                    None => expr.1.take().map(|otherwise| *otherwise),
+                    _ => None,
+                }
+            }
+            ExpressionOperation(expr) => match (expr.0.literal(), expr.1, expr.2.literal()) {
+                (Some(0.0), Operator::Add, None) | (Some(1.0), Operator::Mult, None) => {
+                    Some(std::mem::replace(&mut *expr.2, Error))
+                }
+                (None, Operator::Add | Operator::Sub, Some(0.0)) | (None, Operator::Mult | Operator::Div | Operator::Pow, Some(1.0)) => {
+                    Some(std::mem::replace(&mut *expr.0, Error))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(simplified) = simplified {
+            *self = simplified;
+            return;
+        }
+        let foldable = matches!(self, ExpressionOperation(_) | UnaryOperation(_) | CustomOperation(_) | Primitive(crate::Primitive::Function(_)));
+        if foldable && self.children().iter().all(|child| child.literal().is_some()) {
+            let value = self.perform(&mut Context::with(config, &[], 0.0, 0.0));
+            if let Some(value) = value.ok().filter(|value| value.is_finite()) {
+                *self = number(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rewrite_tests {
+    use crate::{Environment, Moo, Program};
+
+    fn parse(moo: &Moo, source: &str) -> Program {
+        let mut env = Environment::new();
+        env.set("k", 0.0);
+        moo.parse_in(source, &env).ok().unwrap().unwrap()
+    }
+
+    fn simplified(program: Program) -> String {
+        let mut program = program;
+        program.simplify();
+        program.to_string()
+    }
+
+    #[test]
+    fn substitute() {
+        let moo = Moo::new(|_| {});
+        let f = parse(&moo, "sin(x) + x");
+        let composed = f.substitute("x", &parse(&moo, "x^2 + 1")).unwrap();
+        assert_eq!(composed.to_string(), "sin(x ^ 2 + 1) + (x ^ 2 + 1)");
+        assert_eq!(composed.run(2.0), 5f64.sin() + 5.0);
+        // assignments become lets, definitions are carried over
+        let g = parse(&moo, "h(t) = t * 2; a = x + 1; h(a)");
+        let composed = parse(&moo, "x * 3").substitute("x", &g).unwrap();
+        assert_eq!(composed.to_string(), "h(t) = t * 2; (let a = x + 1 in h(a)) * 3");
+        assert_eq!(composed.run(1.0), 12.0);
+        assert!(composed.spans().get(&composed.body).is_none());
+        // shadowed names are left alone, captured ones are an error
+        let f = parse(&moo, "h(x) = x + y; let x = 2 in h(x) + x");
+        assert_eq!(f.substitute("x", &parse(&moo, "y")).unwrap().to_string(), f.to_string());
+        assert_eq!(f.substitute("y", &parse(&moo, "x")).err(), Some("Substitution would capture a variable"));
+        assert_eq!(parse(&moo, "a = 2; a + x").substitute("x", &parse(&moo, "let a = 1 in a")).unwrap().run(0.0), 3.0);
+        assert_eq!(parse(&moo, "h(t) = t + x; h(1)").substitute("x", &parse(&moo, "k")).err(), Some("Unknown variable"));
+        let g = parse(&moo, "h(t) = t; h(x)");
+        assert_eq!(parse(&moo, "h(t) = -t; h(x)").substitute("x", &g).err(), Some("Function is already defined"));
+        assert_eq!(parse(&moo, "h(t) = t; h(x) * 2").substitute("x", &g).unwrap().definitions.len(), 1);
+    }
+
+    #[test]
+    fn bind_and_simplify() {
+        let moo = Moo::new(|_| {});
+        let program = parse(&moo, "k * x + 2 * 3");
+        assert_eq!(program.bind("k", 2.0).to_string(), "2 * x + 2 * 3");
+        assert_eq!(simplified(program.bind("k", 2.0)), "2 * x + 6");
+        assert_eq!(simplified(program.bind("k", 1.0)), "x + 6");
+        assert_eq!(simplified(program.bind("x", -1.5)), "k * -1.5 + 6");
+        assert_eq!(simplified(program.bind("x", 2.0).bind("k", -1.0)), "4");
+        let program = parse(&moo, "f(t) = t ^ 3 / 1; a = k > 1 ? sin(x) : cos(x); let n = k * 2 in f(a) ^ (n / 4)");
+        assert_eq!(simplified(program.bind("k", 2.0)), "f(t) = t ^ 3; a = sin(x); f(a)");
+        assert_eq!(simplified(program.bind("k", 4.0)), "f(t) = t ^ 3; a = sin(x); f(a) ^ 2");
+        assert_eq!(simplified(parse(&moo, "{ x if 1 < 0; 1 if 2 > 1; x otherwise }")), "1");
+        assert_eq!(simplified(parse(&moo, "{ x if 1 < 0; -x if x < 0 }")), "{ -x if x < 0 }");
+        // no branch can match, which stays an error
+        let mut program = parse(&moo, "{ x if 1 < 0 }");
+        assert_eq!(program.try_run(2.0), Err("No piecewise branch matched"));
+        program.simplify();
+        assert_eq!(program.try_run(2.0), Err("No piecewise branch matched"));
+        // values that are not finite and errors are left to evaluation
+        assert_eq!(simplified(parse(&moo, "1 / 0 + (-1)! + sqrt(-1) + x * 1")), "1 / 0 + fact(-1) + sqrt(-1) + x");
+        let program = parse(&moo, "x ^ 2");
+        assert_eq!(program.bind("x", -2.0).to_string(), "(-2) ^ 2");
+        assert_eq!(program.bind("x", -2.0).run(0.0), 4.0);
+    }
+}
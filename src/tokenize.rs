@@ -0,0 +1,146 @@
+// token stream
+// the tokens the parser reads, classified for syntax highlighting
+
+use crate::{abs_bars, constant, Moo, MooError, Span, Token, Tokenizer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Number,
+    Identifier,
+    /// A name followed by `(`, built-in or user-defined.
+    FunctionName,
+    /// `pi`, `π` and `e`.
+    Constant,
+    /// `if`, `otherwise`, `let` and `in`.
+    Keyword,
+    Operator,
+    /// `(`, `)`, `{`, `}` and the bars of `|x|`.
+    Paren,
+    Comma,
+    /// `;` between statements and piecewise branches.
+    Separator,
+    /// A character no token starts with.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Moo<'_> {
+    /// Splits `source` into the tokens [`Moo::parse`] reads, with the custom
+    /// operator symbols and `|` told apart into absolute value bars and bitwise
+    /// or. Whitespace and newlines are skipped, a `|` the parser finds ambiguous
+    /// stays an operator.
+    ///
+    /// A malformed number such as `0b12` gives an error spanning the literal and the
+    /// stream goes on after it, an unknown character is a [`TokenKind::Error`] token.
+    pub fn tokenize(&self, source: &str) -> impl Iterator<Item = Result<SpannedToken, MooError>> {
+        let mut tokenizer = Tokenizer::with_symbols(source, self.operators.symbols());
+        let mut read = Vec::new();
+        loop {
+            match tokenizer.next() {
+                Ok(Some(token)) => read.push(Ok(token)),
+                Ok(None) => break,
+                Err(message) => read.push(Err(tokenizer.literal_error(message))),
+            }
+        }
+        let mut tokens: Vec<(Token, Span)> = read.iter().filter_map(|token| token.clone().ok()).collect();
+        let _ = abs_bars(&mut tokens, self.config.integer_mode);
+        let mut bars = tokens.into_iter();
+        let read: Vec<_> = read.into_iter().map(|token| token.map(|_| bars.next().unwrap())).collect();
+        let mut spanned = Vec::new();
+        for (i, token) in read.iter().enumerate() {
+            let (token, span) = match token {
+                Ok((Token::Newline, _)) => continue,
+                Ok(token) => token.clone(),
+                Err(error) => {
+                    spanned.push(Err(*error));
+                    continue;
+                }
+            };
+            // a name followed by `(` is a function
+            let call = matches!(read.get(i + 1), Some(Ok((Token::LParent, _))));
+            spanned.push(Ok(SpannedToken { kind: kind(&token, call), token, span }));
+        }
+        spanned.into_iter()
+    }
+}
+
+fn kind(token: &Token, call: bool) -> TokenKind {
+    use crate::Token::*;
+    match token {
+        Number(_) => TokenKind::Number,
+        Identifier(_) if call => TokenKind::FunctionName,
+        Identifier(name) if constant(name).is_some() => TokenKind::Constant,
+        Identifier(_) => TokenKind::Identifier,
+        If | Otherwise | Let | In => TokenKind::Keyword,
+        LParent | RParent | LBrace | RBrace | AbsOpen | AbsClose => TokenKind::Paren,
+        Comma => TokenKind::Comma,
+        Semicolon | Newline => TokenKind::Separator,
+        Unknown(_) => TokenKind::Error,
+        _ => TokenKind::Operator,
+    }
+}
+
+#[cfg(test)]
+mod tokenize_tests {
+    use super::*;
+
+    fn tokenize(source: &str) -> Vec<Result<SpannedToken, MooError>> {
+        Moo::new(|_| {}).tokenize(source).collect()
+    }
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source).into_iter().map(|token| token.unwrap().kind).collect()
+    }
+
+    #[test]
+    fn tokenize_kinds() {
+        use TokenKind::*;
+        assert_eq!(kinds("2 * sin(x) + pi"), vec![Number, Operator, FunctionName, Paren, Identifier, Paren, Operator, Constant]);
+        assert_eq!(kinds("f(t) = t; { π if x >= 0 }"), vec![
+            FunctionName, Paren, Identifier, Paren, Operator, Identifier, Separator,
+            Paren, Constant, Keyword, Identifier, Operator, Number, Paren,
+        ]);
+        assert_eq!(kinds("a\nb, 1 $"), vec![Identifier, Identifier, Comma, Number, Error]);
+        assert_eq!(kinds("|x| * |-x|"), vec![Paren, Identifier, Paren, Operator, Paren, Operator, Identifier, Paren]);
+        // as the parser reads them, with the custom symbols and integer mode
+        let mut moo = Moo::new(|_| {});
+        moo.add_operators(|operators| operators.insert_infix("**", crate::operators::POWER, crate::Associativity::Right, f64::powf));
+        let tokens: Vec<SpannedToken> = moo.tokenize("2 ** |x|").map(Result::unwrap).collect();
+        assert_eq!(tokens[1].token, Token::Symbol("**".to_string()));
+        assert_eq!(tokens.iter().map(|token| token.kind).collect::<Vec<_>>(), vec![Number, Operator, Paren, Identifier, Paren]);
+        moo.configure(|config| config.integer_mode = true);
+        let tokens: Vec<Token> = moo.tokenize("x | |y|").map(|token| token.unwrap().token).collect();
+        assert_eq!(tokens[1..], [Token::BitOr, Token::AbsOpen, Token::Identifier("y".to_string()), Token::AbsClose]);
+    }
+
+    #[test]
+    fn tokenize_spans() {
+        let tokens: Vec<SpannedToken> = tokenize("√x × 2").into_iter().map(Result::unwrap).collect();
+        assert_eq!(tokens[0].token, Token::Sqrt);
+        assert_eq!(tokens[1].span, Span { start: 3, end: 4, char_start: 1, char_end: 2 });
+        assert_eq!(tokens[2].span, Span { start: 5, end: 7, char_start: 3, char_end: 4 });
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        let tokens = tokenize("x + 0b12 - 1__0 * y");
+        assert_eq!(tokens.len(), 7);
+        let error = tokens[2].clone().err().unwrap();
+        assert_eq!(error, "Invalid digit for the number base");
+        assert_eq!(error.span, Some(Span { start: 4, end: 8, char_start: 4, char_end: 8 }));
+        assert_eq!(tokens[4].clone().err().unwrap(), "Underscore must be between digits");
+        assert_eq!(tokens[5].clone().unwrap().token, Token::Mult);
+        let error = tokenize("x /* open")[1].clone().err().unwrap();
+        assert_eq!(error, "Unterminated block comment");
+        assert_eq!(error.span, Some(Span { start: 2, end: 9, char_start: 2, char_end: 9 }));
+        // the parser refuses what the stream marks as an error
+        let moo = Moo::new(|_| {});
+        assert_eq!(moo.parse("x $ 1").err().unwrap(), "Unexpected character");
+    }
+}
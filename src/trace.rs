@@ -0,0 +1,164 @@
+// evaluation trace
+// every node evaluated is recorded with its value and the nodes it evaluated,
+// so a NaN can be followed back to the node it started at
+
+use crate::{Context, Environment, Expression, Program, Span, Spans};
+use std::fmt::{Display, Formatter, Result};
+
+/// Every node one run of [`Program::trace`] evaluated, `to_string` prints it as
+/// an annotated tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// Each statement evaluated, by assignment name or `None` for the body.
+    pub statements: Vec<(Option<String>, TraceNode)>,
+}
+
+/// An evaluated node, a function body runs once per call so its nodes appear once per call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceNode {
+    /// The operator, function or variable, as in [`EvalError::operation`](crate::EvalError::operation).
+    pub operation: String,
+    pub span: Option<Span>,
+    pub value: std::result::Result<f64, &'static str>,
+    /// The operands and arguments in evaluation order, a call also has the body it ran.
+    pub children: Vec<TraceNode>,
+}
+
+// a node recorded while evaluating, made a `TraceNode` once the spans are looked up
+pub(crate) struct Traced<'p> {
+    node: &'p Expression,
+    value: std::result::Result<f64, &'static str>,
+    children: Vec<Traced<'p>>,
+}
+
+impl Program {
+    /// Evaluates like [`Program::eval`] and records the value of every node, up to
+    /// the node an error happened at.
+    pub fn trace(&self, env: &mut Environment) -> Trace {
+        let variables = env.copy_variables();
+        let mut ctx = self.context(env, &variables);
+        ctx.trace = Some(Vec::new());
+        // the outcome is the value of the last node traced
+        let _ = self.eval_in(env, &mut ctx);
+        let spans = self.spans();
+        let statements = ctx.trace.take().unwrap().into_iter().enumerate().map(|(i, traced)| {
+            (self.assignments.get(i).map(|a| a.name.clone()), traced.into_node(&spans))
+        });
+        Trace { statements: statements.collect() }
+    }
+}
+
+impl<'p> Context<'p> {
+    // the nodes traced from `mark` on were evaluated by `node`
+    pub(crate) fn record(&mut self, node: &'p Expression, mark: usize, value: std::result::Result<f64, &'static str>) {
+        let trace = self.trace.as_mut().unwrap();
+        let children = trace.split_off(mark);
+        trace.push(Traced { node, value, children });
+    }
+}
+
+impl Traced<'_> {
+    fn into_node(self, spans: &Spans) -> TraceNode {
+        TraceNode {
+            operation: self.node.operation(),
+            span: spans.get(self.node),
+            value: self.value,
+            children: self.children.into_iter().map(|child| child.into_node(spans)).collect(),
+        }
+    }
+}
+
+impl Trace {
+    /// The first node to give `NaN` or an infinity, every node evaluated before it
+    /// and so all of its inputs are finite.
+    pub fn first_non_finite(&self) -> Option<&TraceNode> {
+        self.statements.iter().find_map(|(_, node)| node.first_non_finite())
+    }
+}
+
+impl TraceNode {
+    /// The values this node was computed from.
+    pub fn inputs(&self) -> Vec<f64> {
+        self.children.iter().filter_map(|child| child.value.ok()).collect()
+    }
+    fn first_non_finite(&self) -> Option<&TraceNode> {
+        if let Some(node) = self.children.iter().find_map(|child| child.first_non_finite()) {
+            return Some(node);
+        }
+        match self.value {
+            Ok(value) if !value.is_finite() => Some(self),
+            _ => None,
+        }
+    }
+    fn fmt_tree(&self, f: &mut Formatter<'_>, indent: usize, first: Option<&TraceNode>) -> Result {
+        write!(f, "{:indent$}{}", "", self.operation, indent = indent)?;
+        match self.value {
+            Ok(value) => write!(f, " = {}", value)?,
+            Err(error) => write!(f, " failed: {}", error)?,
+        }
+        if let Some(span) = self.span {
+            write!(f, " [{}..{}]", span.char_start, span.char_end)?;
+        }
+        if first.is_some_and(|first| std::ptr::eq(first, self)) {
+            write!(f, " <- first non-finite")?;
+        }
+        writeln!(f)?;
+        for child in self.children.iter() {
+            child.fmt_tree(f, indent + 2, first)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let first = self.first_non_finite();
+        for (name, node) in self.statements.iter() {
+            writeln!(f, "{}", name.as_deref().unwrap_or("result"))?;
+            node.fmt_tree(f, 2, first)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use crate::{Environment, Moo};
+
+    #[test]
+    fn trace_first_non_finite() {
+        let moo = Moo::new(|_| {});
+        let program = moo.parse("f(t) = sqrt(t) * 2\na = f(x - 3)\na + 1").ok().unwrap().unwrap();
+        let mut env = Environment::new();
+        env.set("x", 1.0);
+        let trace = program.trace(&mut env);
+        let first = trace.first_non_finite().unwrap();
+        assert_eq!(first.operation, "sqrt");
+        assert_eq!(first.inputs(), vec![-2.0]);
+        let span = first.span.unwrap();
+        assert_eq!((span.char_start, span.char_end), (7, 14));
+        assert_eq!(trace.to_string(), [
+            "a",
+            "  f = NaN [23..31]",
+            "    - = -2 [25..30]",
+            "      x = 1 [25..26]",
+            "      3 = 3 [29..30]",
+            "    * = NaN [7..18]",
+            "      sqrt = NaN [7..14] <- first non-finite",
+            "        t = -2 [12..13]",
+            "      2 = 2 [17..18]",
+            "result",
+            "  + = NaN [32..37]",
+            "    a = NaN [32..33]",
+            "    1 = 1 [36..37]",
+            "",
+        ].join("\n"));
+        env.set("x", 7.0);
+        assert_eq!(program.trace(&mut env).first_non_finite(), None);
+        // the trace stops at the node that failed
+        let program = moo.parse("1 + { 1 if x < 0 }").ok().unwrap().unwrap();
+        let trace = program.trace(&mut env);
+        assert_eq!(trace.statements[0].1.value, Err("No piecewise branch matched"));
+        assert_eq!(trace.statements[0].1.children.len(), 2);
+    }
+}
@@ -11,6 +11,15 @@ impl<'a, Item> Iter<'a, Item> {
     pub fn new(vector: &'a Vec<Item>) -> Iter<'a, Item> {
         Iter { index: None, vector }
     }
+    pub fn checkpoint(&self) -> Option<usize> {
+        self.index
+    }
+    pub fn rewind(&mut self, index: Option<usize>) {
+        self.index = index;
+    }
+    pub fn current(&self) -> Option<&'a Item> {
+        self.index.and_then(|i| self.vector.get(i))
+    }
 }
 
 impl<'a, Item> Iterator for Iter<'a, Item> {
@@ -32,7 +41,11 @@ impl<'a, Item> IIter for Iter<'a, Item> {
     fn prev(&mut self) -> Option<&'a Item> {
         let index =
             match self.index {
-                Some(0) | None => return None,
+                None => return None,
+                Some(0) => {
+                    self.index = None;
+                    return None;
+                }
                 Some(i) => i - 1
             };
 